@@ -1,26 +1,85 @@
-use clap::{Parser, Subcommand};
+use clap::{ArgGroup, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use std::path::PathBuf;
 use strum_macros::{Display, EnumIter, EnumString};
 
 #[derive(Parser)]
 #[command(name = "rpio")]
 #[command(about = "Redpencil CLI tool", long_about = None)]
 pub struct Cli {
+    #[arg(long, global = true, value_enum, default_value_t = OutputFormat::Human, help = "Output format of read-only commands")]
+    pub output: OutputFormat,
+    #[arg(short, long, global = true, help = "Hide spinners, hints and other decorative output, hints are also hidden when stdout is not a terminal")]
+    pub quiet: bool,
+    #[arg(short, long, global = true, action = clap::ArgAction::Count, help = "Log the commands that are run to stderr, -vv also logs their stderr")]
+    pub verbose: u8,
+    #[arg(long, global = true, help = "Disable colored output, same as setting NO_COLOR")]
+    pub no_color: bool,
+    #[arg(long, global = true, env = "RPIO_CONFIG", value_name = "PATH", help = "Read the configuration from this file instead of the default location")]
+    pub config: Option<PathBuf>,
+    #[arg(long, global = true, env = "RPIO_CACHE_DIR", value_name = "PATH", help = "Keep the servers cache and other cache files in this folder")]
+    pub cache_dir: Option<PathBuf>,
+    #[arg(long, global = true, env = "RPIO_IDENTITY_FILE", value_name = "PATH", help = "Private key passed as -i to ssh and rsync, overrides identity_file from the config")]
+    pub identity: Option<PathBuf>,
     #[command(subcommand)]
     pub command: CommandsCli,
 }
 
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum OutputFormat {
+    Human,
+    Json,
+}
+
+/// Order of the apps in the picker
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+pub enum PickerSort {
+    Host,
+    App,
+    /// Most recently indexed hosts first
+    Recent,
+}
+
 #[derive(Subcommand, Clone)]
 pub enum CommandsCli {
     #[command(about = "Manage deployed applications")]
+    #[command(group(ArgGroup::new("refreshing").args(["refresh", "refresh_host"])))]
+    #[command(group(ArgGroup::new("host_given").args(["host", "host_arg"])))]
     Apps {
+        #[arg(value_name = "HOST", conflicts_with = "host", help="Same as --host")]
+        host_arg: Option<String>,
+        #[arg(value_name = "APP", conflicts_with = "app_name", help="Same as --app-name")]
+        app_arg: Option<String>,
         #[arg(short, long, help="Re-index all hosts configured in your ssh config")]
         refresh: bool,
-        #[arg(long, help="Not implemented yet")]
+        #[arg(long, value_name = "HOST", help="Re-index only this host and keep the rest of the cache")]
+        refresh_host: Option<String>,
+        #[arg(long, requires = "refreshing", help="Also cache the primary container of every app while refreshing")]
+        deep: bool,
+        #[arg(long, conflicts_with = "refreshing", help="Index all hosts without reading or writing the cache")]
+        no_cache: bool,
+        #[arg(long, help="Fetch the app's containers again instead of using the container cache")]
+        refresh_containers: bool,
+        #[arg(long, help="Also offer stopped containers when picking one")]
+        include_stopped: bool,
+        #[arg(long, help="Print the commands that would be executed without running them")]
         dry_run: bool,
         #[arg(long, help="Server where the app is hosted")]
         host: Option<String>,
+        #[arg(long, requires = "host_given", help="Use --host even when it is not a host from your ssh config")]
+        no_validate_host: bool,
         #[arg(long, help="Name of the hosted application")]
         app_name: Option<String>,
+        #[arg(long, value_name = "HOST", help="Skip hosts matching this pattern for this run only, added to ignore_hosts")]
+        exclude: Vec<String>,
+        #[arg(long, value_name = "SUBSTR", help="Only show apps on hosts whose name contains this text in the picker")]
+        host_filter: Option<String>,
+        #[arg(long, help="Leave hosts that could not be reached while indexing out of the picker")]
+        only_reachable: bool,
+        #[arg(long, conflicts_with_all = ["app_name", "app_arg"], help="Pick several apps in the picker and run restart or ps on each of them")]
+        multi: bool,
+        #[arg(long, value_enum, default_value_t = PickerSort::Host, help="Order of the apps in the picker")]
+        sort: PickerSort,
         #[command(subcommand)]
         app_command: Option<ApplicationCommandCli>,
     },
@@ -29,6 +88,34 @@ pub enum CommandsCli {
         #[command(subcommand)]
         command: ConfigCommand,
     },
+    #[command(about = "Manage the servers cache")]
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommand,
+    },
+    #[command(about = "Manage tunnels running in the background", visible_alias = "tunnel")]
+    Tunnels {
+        #[command(subcommand)]
+        command: TunnelsCommand,
+    },
+    #[command(about = "Check that every configured host can be reached over ssh")]
+    CheckHosts,
+    #[command(about = "Check that the tools, ssh config and folders rpio needs are available")]
+    Doctor,
+    #[command(about = "Generate shell completions", hide = true)]
+    Completions {
+        #[arg(help = "Shell to generate completions for")]
+        shell: Shell,
+    },
+    #[command(about = "Print the compose config of a picker line, used for the fzf preview", hide = true)]
+    Preview {
+        line: String,
+    },
+    #[command(name = "__complete", about = "Print hosts or apps from the cache for dynamic shell completion", hide = true)]
+    Complete {
+        #[command(subcommand)]
+        target: CompleteTarget,
+    },
 }
 
 #[derive(Debug, Clone, EnumIter, EnumString, Display, Subcommand)]
@@ -40,21 +127,175 @@ pub enum ApplicationCommandCli {
     Tunnel {
         #[arg(long)]
         container_name: Option<String>,
-        #[arg(long)]
-        host_port: Option<u32>,
-        #[arg(long)]
-        remote_port: Option<u32>,
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        host_port: Option<u16>,
+        #[arg(long, conflicts_with = "host_port", help = "Use a free local port chosen by the OS")]
+        auto_port: bool,
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        remote_port: Option<u16>,
+        #[arg(long, help = "Forward to the port the container publishes on the host instead of the container IP")]
+        via_published: bool,
+        #[arg(long, help = "Keep the tunnel open in the background and return to the shell")]
+        background: bool,
+    },
+    #[command(about="Copy a backup from the specified remote app to your local app")]
+    RetrieveBackup {
+        #[arg(long, help="Copy all backups instead of picking one")]
+        all: bool,
+        #[arg(long, value_name = "KBPS", help="Limit the transfer speed, overrides rsync_bwlimit from the config")]
+        bwlimit: Option<u32>,
+        #[arg(long, help="Only print what rsync would transfer")]
+        preview: bool,
+        #[arg(long, value_name = "PATH", help="Copy into PATH/data/... instead of the local app found from the current directory")]
+        dest: Option<PathBuf>,
     },
-    #[command(about="Copy all backup files from the specified remote app to your local app")]
-    RetrieveBackup,
     #[command(about="Copy all files from the specified remote app to your local app")]
-    RetrieveFiles,
+    RetrieveFiles {
+        #[arg(long, value_name = "KBPS", help="Limit the transfer speed, overrides rsync_bwlimit from the config")]
+        bwlimit: Option<u32>,
+        #[arg(long, help="Only print what rsync would transfer")]
+        preview: bool,
+        #[arg(long, value_name = "PATH", help="Copy into PATH/data/... instead of the local app found from the current directory")]
+        dest: Option<PathBuf>,
+    },
     #[command(about="Retrieve and display the URL where the app is hosted")]
-    HostedUrl,
+    HostedUrl {
+        #[arg(long, help="Also open the URL in your browser, picks one when the app has several")]
+        open: bool,
+        #[arg(long, conflicts_with = "open", help="List the URLs of every app in the cache instead of a single app, --host limits it to one host")]
+        all_hosts: bool,
+    },
+    #[command(about="Show the logs of the specified app or one of its containers")]
+    Logs {
+        #[arg(long)]
+        container_name: Option<String>,
+        #[arg(short, long, help="Keep streaming new log output")]
+        follow: bool,
+        #[arg(long, help="Number of lines to show from the end of the logs")]
+        tail: Option<u32>,
+    },
+    #[command(about="Restart the specified app or one of its containers")]
+    Restart {
+        #[arg(long)]
+        container_name: Option<String>,
+        #[arg(short, long, help="Do not ask for confirmation")]
+        yes: bool,
+    },
+    #[command(about="Show the status of the app's containers")]
+    Ps,
+    #[command(about="Run a command inside one of the app's containers, defaults to a shell")]
+    Exec {
+        #[arg(long)]
+        container_name: Option<String>,
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, help="Command to run inside the container")]
+        command: Vec<String>,
+    },
+    #[command(about="Show the environment variables of one of the app's services")]
+    Env {
+        #[arg(long, help="Compose service to show, picked when there are several")]
+        service: Option<String>,
+        #[arg(long, value_name = "KEY=VALUE", help="Write a variable to the app's .env on the host instead, can be repeated")]
+        set: Vec<String>,
+        #[arg(short, long, requires = "set", help="Do not ask for confirmation before writing .env")]
+        yes: bool,
+    },
+    #[command(about="Run a command on the host in the app's directory, keeps its exit code")]
+    Run {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true, help="Command to run, passed to the remote shell like ssh does")]
+        command: Vec<String>,
+    },
+    // Not an action on a single app, so it is left out of the interactive command picker
+    #[strum(disabled)]
+    #[command(about="Print all indexed apps without launching the picker")]
+    List {
+        #[arg(long, help="Only list the apps hosted on this server")]
+        host: Option<String>,
+        #[arg(long, help="Print the apps as json, same as --output json")]
+        json: bool,
+        #[arg(long, conflicts_with = "json", value_name = "TEMPLATE", help="Print every app using a template such as '{host}\\t{app}', placeholders: {host}, {app}, {name}, {root}, {dir} and {container}")]
+        format: Option<String>,
+    },
 }
 
 #[derive(Subcommand, Clone)]
 pub enum ConfigCommand {
     #[command(about = "Create initial configuration file")]
     Init,
+    #[command(about = "Print the location of the configuration and cache files")]
+    Path,
+    #[command(about = "Print the effective configuration, including defaults")]
+    Show,
+    #[command(about = "Remember the container and port to tunnel to for an app")]
+    SetDefault {
+        #[arg(long, help = "Server where the app is hosted")]
+        host: String,
+        #[arg(long, help = "Name of the hosted application")]
+        app_name: String,
+        #[arg(long, required_unless_present = "remote_port")]
+        container_name: Option<String>,
+        #[arg(long, value_parser = clap::value_parser!(u16).range(1..))]
+        remote_port: Option<u16>,
+    },
+    #[command(about = "Add a host to ignore_hosts")]
+    IgnoreHost {
+        #[arg(help = "Host alias from your ssh config")]
+        host: String,
+    },
+    #[command(about = "Remove a host from ignore_hosts")]
+    UnignoreHost {
+        #[arg(help = "Host alias from your ssh config")]
+        host: String,
+    },
+    #[command(about = "Manage short names for apps you use often")]
+    Alias {
+        #[command(subcommand)]
+        command: AliasCommand,
+    },
+}
+
+#[derive(Subcommand, Clone)]
+pub enum AliasCommand {
+    #[command(about = "Add or replace an alias")]
+    Add {
+        #[arg(help = "Short name to pass as --app-name")]
+        name: String,
+        #[arg(value_name = "HOST:APP", help = "App the alias expands to, with or without its host")]
+        target: String,
+    },
+    #[command(about = "Remove an alias")]
+    Remove {
+        name: String,
+    },
+}
+
+#[derive(Subcommand, Clone, Copy)]
+pub enum CompleteTarget {
+    #[command(about = "Every cached host, one per line")]
+    Hosts,
+    #[command(about = "Every cached app as host:app, one per line")]
+    Apps,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum CacheCommand {
+    #[command(about = "Delete the servers cache so the next run re-indexes all hosts")]
+    Clear {
+        #[arg(short, long, help = "Do not ask for confirmation")]
+        yes: bool,
+    },
+    #[command(about = "Show when each host was last indexed and how many apps it has")]
+    Info,
+}
+
+#[derive(Subcommand, Clone)]
+pub enum TunnelsCommand {
+    #[command(about = "Show the tunnels started with --background that are still running")]
+    List,
+    #[command(about = "Stop a background tunnel, picks one when no port is given")]
+    Stop {
+        #[arg(help = "Local port of the tunnel")]
+        port: Option<u16>,
+        #[arg(long, conflicts_with = "port", help = "Stop all background tunnels")]
+        all: bool,
+    },
 }
@@ -0,0 +1,66 @@
+use crate::remote_app::Container;
+use crate::{cache_dir, write_atomically};
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static BYPASS: AtomicBool = AtomicBool::new(false);
+
+/// Makes `cached_containers` miss, freshly fetched containers are still stored
+pub fn bypass_container_cache() {
+    BYPASS.store(true, Ordering::Relaxed);
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct ContainerCache {
+    apps: BTreeMap<String, ContainerEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ContainerEntry {
+    fetched_at: i64, // unix timestamp
+    containers: Vec<Container>,
+}
+
+pub fn container_cache_path() -> PathBuf {
+    cache_dir().join("containers.toml")
+}
+
+fn load() -> ContainerCache {
+    fs::read_to_string(container_cache_path())
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Containers fetched for `key` less than `ttl_seconds` ago
+pub fn cached_containers(key: &str, ttl_seconds: u64) -> Option<Vec<Container>> {
+    if BYPASS.load(Ordering::Relaxed) {
+        return None;
+    }
+
+    let entry = load().apps.remove(key)?;
+    let age = Utc::now().timestamp().saturating_sub(entry.fetched_at);
+    if age < i64::try_from(ttl_seconds).unwrap_or(i64::MAX) {
+        Some(entry.containers)
+    } else {
+        None
+    }
+}
+
+pub fn store_containers(key: &str, containers: &[Container]) -> Result<()> {
+    let mut cache = load();
+    cache.apps.insert(
+        key.to_owned(),
+        ContainerEntry {
+            fetched_at: Utc::now().timestamp(),
+            containers: containers.to_vec(),
+        },
+    );
+
+    write_atomically(&container_cache_path(), &toml::to_string_pretty(&cache)?)
+}
@@ -0,0 +1,50 @@
+use regex::{Captures, Regex};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::path::Path;
+
+/// Variables from a compose `.env` file, a missing file has no variables
+pub fn read_env_file(path: &Path) -> BTreeMap<String, String> {
+    let Ok(contents) = fs::read_to_string(path) else {
+        return BTreeMap::new();
+    };
+
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            let (key, value) = line.split_once('=')?;
+            let value = value.trim();
+            let value = value
+                .strip_prefix('"')
+                .and_then(|v| v.strip_suffix('"'))
+                .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+                .unwrap_or(value);
+            Some((key.trim().to_owned(), value.to_owned()))
+        })
+        .collect()
+}
+
+/// Substitutes `${VAR}` references, unknown variables are left in place with a warning
+pub fn interpolate(text: &str, vars: &BTreeMap<String, String>) -> String {
+    let re = Regex::new(r"\$\{([A-Za-z_][A-Za-z0-9_]*)\}").unwrap();
+    let mut missing = BTreeSet::new();
+
+    let interpolated = re
+        .replace_all(text, |captures: &Captures| match vars.get(&captures[1]) {
+            Some(value) => value.to_owned(),
+            None => {
+                missing.insert(captures[1].to_owned());
+                captures[0].to_owned()
+            }
+        })
+        .to_string();
+
+    for name in missing {
+        eprintln!("Warning: ${{{name}}} is not set in .env, leaving it as is");
+    }
+
+    interpolated
+}
@@ -1,17 +1,94 @@
-use std::io::Write;
+use crate::shell::LoggedCommand;
+use dialoguer::{FuzzySelect, MultiSelect};
+use std::io::{self, ErrorKind, Write};
 use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
 
-pub fn run_fzf(lines: &[String], prompt: &str, query: &str) -> anyhow::Result<Option<String>> {
-    let mut child = Command::new("fzf")
-        .args([
-            "--query",
-            &format!("{query} "),
-            "--ansi",
-            &format!("--prompt={prompt} > "),
-        ])
+static BUILTIN_SELECTOR: AtomicBool = AtomicBool::new(false);
+
+/// Makes `run_fzf` use the built-in selector even when fzf is installed
+pub fn prefer_builtin_selector() {
+    BUILTIN_SELECTOR.store(true, Ordering::Relaxed);
+}
+
+/// Anything after a tab in a line is hidden, but still part of the returned selection.
+/// `header` is shown above the lines to explain what is being picked.
+/// `preview` is an fzf `--preview` command template such as `cat {}`,
+/// the built-in selector has no preview window and ignores it
+pub fn run_fzf(
+    lines: &[String],
+    prompt: &str,
+    header: Option<&str>,
+    query: &str,
+    preview: Option<&str>,
+) -> anyhow::Result<Option<String>> {
+    if BUILTIN_SELECTOR.load(Ordering::Relaxed) {
+        return run_builtin_selector(lines, prompt, header, query);
+    }
+
+    match spawn_fzf(lines, prompt, header, query, preview, false) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            run_builtin_selector(lines, prompt, header, query)
+        }
+        selected => Ok(selected?),
+    }
+}
+
+/// Like `run_fzf`, but lets the user mark several lines with tab, an empty list means cancelled
+pub fn run_fzf_multi(
+    lines: &[String],
+    prompt: &str,
+    header: Option<&str>,
+    query: &str,
+    preview: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    if BUILTIN_SELECTOR.load(Ordering::Relaxed) {
+        return run_builtin_multi_selector(lines, prompt, header);
+    }
+
+    match spawn_fzf(lines, prompt, header, query, preview, true) {
+        Err(error) if error.kind() == ErrorKind::NotFound => {
+            run_builtin_multi_selector(lines, prompt, header)
+        }
+        selected => Ok(selected?
+            .map(|selected| selected.lines().map(str::to_owned).collect())
+            .unwrap_or_default()),
+    }
+}
+
+/// `None` when fzf was cancelled or nothing matched, with `multi` the selected lines are separated by newlines.
+/// Any other exit code of fzf is an error.
+fn spawn_fzf(
+    lines: &[String],
+    prompt: &str,
+    header: Option<&str>,
+    query: &str,
+    preview: Option<&str>,
+    multi: bool,
+) -> io::Result<Option<String>> {
+    let mut command = Command::new("fzf");
+    command.args([
+        "--query",
+        &format!("{query} "),
+        "--ansi",
+        "--delimiter=\t",
+        "--with-nth=1",
+        &format!("--prompt={prompt} > "),
+    ]);
+    if let Some(header) = header {
+        command.args(["--header", header]);
+    }
+    if let Some(preview) = preview {
+        command.args(["--preview", preview, "--preview-window=right,50%"]);
+    }
+    if multi {
+        command.arg("--multi");
+    }
+
+    let mut child = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
-        .spawn()?;
+        .logged_spawn()?;
 
     {
         let stdin = child.stdin.as_mut().unwrap();
@@ -22,15 +99,67 @@ pub fn run_fzf(lines: &[String], prompt: &str, query: &str) -> anyhow::Result<Op
 
     let output = child.wait_with_output()?;
 
-    if output.status.success() {
-        let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    match output.status.code() {
+        Some(0) => {
+            let selected = String::from_utf8_lossy(&output.stdout).trim().to_string();
 
-        if selected.is_empty() {
-            Ok(None)
-        } else {
-            Ok(Some(selected))
+            if selected.is_empty() {
+                Ok(None)
+            } else {
+                Ok(Some(selected))
+            }
         }
-    } else {
-        Ok(None)
+        // No match and cancelled with escape or ctrl-c
+        Some(1 | 130) => Ok(None),
+        _ => Err(io::Error::other(format!("fzf failed ({})", output.status))),
     }
 }
+
+/// Fallback for when fzf is not available, returns the selected line unchanged like fzf does
+fn run_builtin_selector(
+    lines: &[String],
+    prompt: &str,
+    header: Option<&str>,
+    query: &str,
+) -> anyhow::Result<Option<String>> {
+    print_header(header);
+    let selection = FuzzySelect::new()
+        .with_prompt(prompt)
+        .with_initial_text(query)
+        .items(visible_parts(lines))
+        .interact_opt()?;
+
+    Ok(selection.map(|index| lines[index].to_owned()))
+}
+
+fn run_builtin_multi_selector(
+    lines: &[String],
+    prompt: &str,
+    header: Option<&str>,
+) -> anyhow::Result<Vec<String>> {
+    print_header(header);
+    let selection = MultiSelect::new()
+        .with_prompt(prompt)
+        .items(visible_parts(lines))
+        .interact_opt()?;
+
+    Ok(selection
+        .unwrap_or_default()
+        .into_iter()
+        .map(|index| lines[index].to_owned())
+        .collect())
+}
+
+/// The built-in selectors draw on stderr, the header goes right above them
+fn print_header(header: Option<&str>) {
+    if let Some(header) = header {
+        eprintln!("{header}");
+    }
+}
+
+fn visible_parts(lines: &[String]) -> Vec<&str> {
+    lines
+        .iter()
+        .map(|line| line.split('\t').next().unwrap_or_default())
+        .collect()
+}
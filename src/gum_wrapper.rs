@@ -1,21 +1,52 @@
-use anyhow::{Result, bail};
+use crate::shell::LoggedCommand;
+use crate::tools::tool_error;
+use anyhow::{Result, anyhow, bail};
 use std::process::{Command, Stdio};
 
-pub fn prompt_number(prompt: &str) -> Result<u32> {
+pub fn prompt_port(prompt: &str) -> Result<u16> {
+    prompt_optional_port(prompt, "Enter a port...")?.ok_or_else(|| anyhow!("No port was entered"))
+}
+
+/// Same as `prompt_port`, but returns `None` when the input is left empty
+pub fn prompt_optional_port(prompt: &str, placeholder: &str) -> Result<Option<u16>> {
+    let input_str = prompt_string(prompt, placeholder)?;
+
+    if input_str.is_empty() {
+        return Ok(None);
+    }
+
+    match input_str.parse::<u16>() {
+        Ok(port) if port > 0 => Ok(Some(port)),
+        _ => bail!("'{input_str}' is not a valid port, expected a number between 1 and 65535"),
+    }
+}
+
+/// Free text input, an empty string when nothing was entered
+pub fn prompt_string(prompt: &str, placeholder: &str) -> Result<String> {
     let output = Command::new("gum")
         .arg("input")
         .arg("--placeholder")
-        .arg("Enter a number...")
+        .arg(placeholder)
         .arg("--header")
-        .arg(&prompt)
+        .arg(prompt)
         .stderr(Stdio::inherit())
-        .output()?;
+        .logged_output()
+        .map_err(tool_error("gum"))?;
 
     if !output.status.success() {
         bail!("gum was cancelled");
     }
 
-    let input_str = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+}
+
+/// `false` when the user answered no or cancelled the prompt
+pub fn prompt_confirm(question: &str) -> Result<bool> {
+    let status = Command::new("gum")
+        .arg("confirm")
+        .arg(question)
+        .logged_status()
+        .map_err(tool_error("gum"))?;
 
-    Ok(input_str.parse::<u32>()?)
+    Ok(status.success())
 }
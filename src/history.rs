@@ -0,0 +1,100 @@
+use crate::remote_app::RemoteApp;
+use crate::{cache_dir, write_atomically};
+use anyhow::Result;
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Apps that were not used for a while are forgotten once the history grows beyond this
+const MAX_HISTORY_ENTRIES: usize = 100;
+
+#[derive(Default, Serialize, Deserialize)]
+struct History {
+    apps: BTreeMap<String, HistoryEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct HistoryEntry {
+    count: u32,
+    last_used: i64, // unix timestamp
+    /// Offered first the next time a command is picked for the app, e.g. `ssh-session`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    last_command: Option<String>,
+}
+
+impl HistoryEntry {
+    /// Uses weigh more the more recent they are, like `z` and `fasd` do
+    fn frecency(&self, now: i64) -> f64 {
+        let weight = match now.saturating_sub(self.last_used) {
+            ..3600 => 4.0,
+            3600..86400 => 2.0,
+            86400..604800 => 1.0,
+            _ => 0.25,
+        };
+        f64::from(self.count) * weight
+    }
+}
+
+pub fn history_path() -> PathBuf {
+    cache_dir().join("history.toml")
+}
+
+/// Same key as the picker lines carry in their hidden fields
+pub fn history_key(host: &str, qualified_name: &str) -> String {
+    format!("{host}:{qualified_name}")
+}
+
+fn load() -> History {
+    fs::read_to_string(history_path())
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default()
+}
+
+pub fn record_app_use(remote_app: &RemoteApp, command: &str) -> Result<()> {
+    let mut history = load();
+    let now = Utc::now().timestamp();
+    let entry = history
+        .apps
+        .entry(history_key(&remote_app.host, &remote_app.qualified_name()))
+        .or_insert(HistoryEntry {
+            count: 0,
+            last_used: now,
+            last_command: None,
+        });
+    entry.count = entry.count.saturating_add(1);
+    entry.last_used = now;
+    entry.last_command = Some(command.to_owned());
+
+    if history.apps.len() > MAX_HISTORY_ENTRIES {
+        let mut last_used: Vec<i64> = history.apps.values().map(|e| e.last_used).collect();
+        last_used.sort_unstable_by(|a, b| b.cmp(a));
+        let oldest_kept = last_used[MAX_HISTORY_ENTRIES - 1];
+        history.apps.retain(|_, e| e.last_used >= oldest_kept);
+    }
+
+    write_atomically(&history_path(), &toml::to_string_pretty(&history)?)
+}
+
+/// Command that was last run on the app, `None` for apps that were never used
+pub fn last_app_command(remote_app: &RemoteApp) -> Option<String> {
+    load()
+        .apps
+        .remove(&history_key(&remote_app.host, &remote_app.qualified_name()))?
+        .last_command
+}
+
+/// Frecency of the used apps by `history_key`, higher is more likely to be picked again
+pub fn app_frecencies() -> BTreeMap<String, f64> {
+    let now = Utc::now().timestamp();
+    load()
+        .apps
+        .into_iter()
+        .map(|(key, entry)| {
+            let frecency = entry.frecency(now);
+            (key, frecency)
+        })
+        .collect()
+}
@@ -1,30 +1,64 @@
 mod cli;
+mod container_cache;
+mod dotenv;
 mod fzf;
 mod gum_wrapper;
+mod history;
+mod patterns;
 mod remote_app;
+mod shell;
 mod spinner;
+mod ssh;
+mod ssh_config;
+mod table;
+mod tools;
+mod tunnels;
 
-use crate::cli::{ApplicationCommandCli, Cli, CommandsCli, ConfigCommand};
-use crate::fzf::run_fzf;
-use crate::gum_wrapper::prompt_number;
-use crate::remote_app::RemoteApp;
-use crate::spinner::create_and_start_spinner;
+use crate::cli::{
+    AliasCommand, ApplicationCommandCli, CacheCommand, Cli, CommandsCli, CompleteTarget,
+    ConfigCommand, OutputFormat, PickerSort, TunnelsCommand,
+};
+use crate::container_cache::{bypass_container_cache, container_cache_path};
+use crate::dotenv::{interpolate, read_env_file};
+use crate::fzf::{prefer_builtin_selector, run_fzf, run_fzf_multi};
+use crate::gum_wrapper::{prompt_confirm, prompt_optional_port, prompt_port, prompt_string};
+use crate::history::{app_frecencies, history_key, history_path, last_app_command, record_app_use};
+use crate::patterns::build_glob_set;
+use crate::remote_app::{RemoteApp, docker_compose};
+use crate::shell::{LoggedCommand, command_argv, format_command, quote};
+use crate::spinner::{
+    create_and_start_spinner, create_and_start_timed_spinner, hide_spinners, set_progress,
+};
+use crate::ssh::ssh_command;
+use crate::ssh_config::{SshHost, read_ssh_hosts, ssh_config_path};
+use crate::table::print_table;
+use crate::tools::{install_hint, tool_error, tool_version};
+use crate::tunnels::{
+    BackgroundTunnel, load_tunnels, register_tunnel, stop_tunnel, tunnels_state_path,
+};
 use ansi_term::Style;
 use anyhow::Result;
 use anyhow::anyhow;
 use anyhow::bail;
 use chrono::Utc;
-use clap::Parser;
+use clap::{CommandFactory, Parser};
+use clap_complete::Shell;
 use directories::ProjectDirs;
+use log::LevelFilter;
 use regex::Regex;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize};
 use serde_yaml::Value;
 use std::collections::BTreeMap;
 use std::fs;
-use std::io::Write;
-use std::path::PathBuf;
-use std::process::{Command, Stdio};
-use std::str::FromStr;
+use std::io::{BufRead, BufReader, IsTerminal, Read, Write};
+use std::net::{IpAddr, TcpListener};
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
 use strum::IntoEnumIterator;
 use strum_macros::Display;
 use tempfile::NamedTempFile;
@@ -35,9 +69,41 @@ enum Commands {
         remote_app: RemoteApp,
         app_command: ApplicationCommand,
     },
+    MultiApps {
+        dry_run: bool,
+        remote_apps: Vec<RemoteApp>,
+        app_command: ApplicationCommand,
+    },
     Config {
         command: ConfigCommand,
     },
+    Cache {
+        command: CacheCommand,
+    },
+    Tunnels {
+        command: TunnelsCommand,
+    },
+    CheckHosts,
+    Doctor,
+    Completions {
+        shell: Shell,
+    },
+    Preview {
+        line: String,
+    },
+    Complete {
+        target: CompleteTarget,
+    },
+    ListApps {
+        no_cache: bool,
+        host: Option<String>,
+        json: bool,
+        format: Option<String>,
+    },
+    AllHostedUrls {
+        no_cache: bool,
+        host: Option<String>,
+    },
 }
 
 #[derive(Display)]
@@ -47,12 +113,50 @@ enum ApplicationCommand {
     SshSession,
     Tunnel {
         container_name: String,
-        host_port: u32,
-        remote_port: u32,
+        host_port: u16,
+        remote_port: u16,
+        via_published: bool,
+        background: bool,
+    },
+    RetrieveBackup {
+        /// `None` copies the whole backups folder
+        backup: Option<String>,
+        bwlimit: Option<u32>,
+        preview: bool,
+        /// Used instead of the local app's folder
+        dest: Option<PathBuf>,
+    },
+    RetrieveFiles {
+        bwlimit: Option<u32>,
+        preview: bool,
+        dest: Option<PathBuf>,
+    },
+    HostedUrl {
+        open: bool,
+    },
+    Logs {
+        container_name: Option<String>,
+        follow: bool,
+        tail: Option<u32>,
+    },
+    Restart {
+        container_name: Option<String>,
+        yes: bool,
+    },
+    Ps,
+    Exec {
+        container_name: String,
+        command: Vec<String>,
+    },
+    Run {
+        command: Vec<String>,
+    },
+    Env {
+        service: Option<String>,
+        /// Variables to write to `.env`, the variables are listed when empty
+        set: Vec<(String, String)>,
+        yes: bool,
     },
-    RetrieveBackup,
-    RetrieveFiles,
-    HostedUrl,
 }
 
 impl Commands {
@@ -61,37 +165,185 @@ impl Commands {
             CommandsCli::Config { command } => Ok(Commands::Config {
                 command: command.to_owned(),
             }),
+            CommandsCli::Cache { command } => Ok(Commands::Cache {
+                command: command.to_owned(),
+            }),
+            CommandsCli::Tunnels { command } => Ok(Commands::Tunnels {
+                command: command.to_owned(),
+            }),
+            CommandsCli::CheckHosts => Ok(Commands::CheckHosts),
+            CommandsCli::Doctor => Ok(Commands::Doctor),
+            CommandsCli::Completions { shell } => Ok(Commands::Completions { shell: *shell }),
+            CommandsCli::Preview { line } => Ok(Commands::Preview {
+                line: line.to_owned(),
+            }),
+            CommandsCli::Complete { target } => Ok(Commands::Complete { target: *target }),
             CommandsCli::Apps {
                 refresh,
+                refresh_host,
+                deep,
+                no_cache,
+                refresh_containers,
+                include_stopped,
                 dry_run,
                 host,
+                no_validate_host,
                 app_name,
+                host_filter,
+                only_reachable,
+                multi,
+                sort,
                 app_command,
+                // `exclude` is already merged into the config's ignore_hosts
+                exclude: _,
+                // moved into `host` and `app_name` by `merge_positional_app`
+                host_arg: _,
+                app_arg: _,
             } => {
-                let app_command = match app_command {
-                    Some(app_command) => app_command.to_owned(),
-                    None => choose_application_command()?,
-                };
-
+                if *refresh_containers {
+                    bypass_container_cache();
+                }
+                // With --multi the host is only the initial picker query
+                if let Some(host) = host
+                    && !*no_validate_host
+                    && !*multi
+                {
+                    validate_host(config, host)?;
+                }
                 if *refresh {
-                    let cache = fetch_servers_cache(&config.ignore_hosts)?;
+                    ensure_servers_cache_not_newer()?;
+                    let cache = fetch_servers_cache(config, *deep)?;
                     write_servers_cache(&cache)?;
                 }
-                let remote_app = match (&host, &app_name) {
-                    (Some(host), Some(app_name)) => {
-                        Ok(Some(RemoteApp::new(host.to_string(), app_name.to_string())))
+                if let Some(refresh_host) = refresh_host {
+                    refresh_single_host(config, refresh_host, *deep)?;
+                }
+
+                if let Some(ApplicationCommandCli::List {
+                    host: list_host,
+                    json,
+                    format,
+                }) = app_command
+                {
+                    return Ok(Commands::ListApps {
+                        no_cache: *no_cache,
+                        host: list_host.to_owned().or(host.to_owned()),
+                        json: *json,
+                        format: format.to_owned(),
+                    });
+                }
+                if let Some(ApplicationCommandCli::HostedUrl {
+                    all_hosts: true, ..
+                }) = app_command
+                {
+                    return Ok(Commands::AllHostedUrls {
+                        no_cache: *no_cache,
+                        host: host.to_owned(),
+                    });
+                }
+
+                if *multi {
+                    let app_command = match app_command {
+                        Some(app_command) => app_command.to_owned(),
+                        None => choose_application_command(None)?,
+                    };
+                    let command_name = app_command.to_string();
+                    // Containers are not picked per app, without a name the whole app is restarted
+                    let app_command = match app_command {
+                        ApplicationCommandCli::Restart {
+                            container_name,
+                            yes,
+                        } => ApplicationCommand::Restart {
+                            container_name,
+                            yes,
+                        },
+                        ApplicationCommandCli::Ps => ApplicationCommand::Ps,
+                        app_command => {
+                            bail!("--multi only supports restart and ps, not {app_command}")
+                        }
+                    };
+                    let query = host.as_deref().unwrap_or("");
+                    let remote_apps = prompt_remote_apps(
+                        config,
+                        query,
+                        *no_cache,
+                        host_filter.as_deref(),
+                        *only_reachable,
+                        *sort,
+                    )?;
+                    if remote_apps.is_empty() {
+                        bail!("No apps selected");
                     }
-                    (Some(host), None) => prompt_remote_app(&config, host),
-                    (None, Some(app_name)) => prompt_remote_app(&config, app_name),
-                    (None, None) => prompt_remote_app(&config, ""),
+                    for remote_app in &remote_apps {
+                        remember_app_use(remote_app, &command_name);
+                    }
+
+                    return Ok(Commands::MultiApps {
+                        dry_run: *dry_run,
+                        remote_apps,
+                        app_command,
+                    });
+                }
+
+                let remote_app = match (&host, &app_name) {
+                    (Some(host), Some(app_name)) => RemoteApp::from_host_and_app(host, app_name)
+                        .and_then(|remote_app| resolve_app_name(config, remote_app))
+                        .map(Some),
+                    (Some(host), None) => prompt_remote_app(
+                        config,
+                        host,
+                        *no_cache,
+                        host_filter.as_deref(),
+                        *only_reachable,
+                        *sort,
+                    ),
+                    (None, Some(app_name)) => prompt_remote_app(
+                        config,
+                        app_name,
+                        *no_cache,
+                        host_filter.as_deref(),
+                        *only_reachable,
+                        *sort,
+                    ),
+                    (None, None) => prompt_remote_app(
+                        config,
+                        "",
+                        *no_cache,
+                        host_filter.as_deref(),
+                        *only_reachable,
+                        *sort,
+                    ),
                 };
 
                 let remote_app = remote_app?.ok_or_else(|| anyhow!("Could not find any apps"))?;
 
+                // Picked after the app, so the command that was last run on it can be offered first
+                let app_command = match app_command {
+                    Some(app_command) => app_command.to_owned(),
+                    None => {
+                        let last_command = last_app_command(&remote_app);
+                        match choose_application_command(last_command.as_deref())? {
+                            ApplicationCommandCli::Exec { container_name, .. } => {
+                                ApplicationCommandCli::Exec {
+                                    container_name,
+                                    command: prompt_exec_command()?,
+                                }
+                            }
+                            app_command => app_command,
+                        }
+                    }
+                };
+                remember_app_use(&remote_app, &app_command.to_string());
+
                 Ok(Commands::Apps {
                     dry_run: *dry_run,
                     remote_app: remote_app.to_owned(),
-                    app_command: ApplicationCommand::build(app_command, &remote_app)?,
+                    app_command: ApplicationCommand::build(
+                        config,
+                        app_command,
+                        &remote_app,
+                        *include_stopped,
+                    )?,
                 })
             }
         }
@@ -99,41 +351,259 @@ impl Commands {
 }
 
 impl ApplicationCommand {
-    fn build(value: ApplicationCommandCli, remote_app: &RemoteApp) -> Result<Self> {
+    fn build(
+        config: &Config,
+        value: ApplicationCommandCli,
+        remote_app: &RemoteApp,
+        include_stopped: bool,
+    ) -> Result<Self> {
         match value {
-            ApplicationCommandCli::HostedUrl => Ok(ApplicationCommand::HostedUrl),
-            ApplicationCommandCli::RetrieveBackup => Ok(ApplicationCommand::RetrieveBackup),
-            ApplicationCommandCli::RetrieveFiles => Ok(ApplicationCommand::RetrieveFiles),
+            ApplicationCommandCli::HostedUrl { open, .. } => {
+                Ok(ApplicationCommand::HostedUrl { open })
+            }
+            ApplicationCommandCli::RetrieveBackup {
+                all,
+                bwlimit,
+                preview,
+                dest,
+            } => {
+                let backup = if all {
+                    None
+                } else {
+                    Some(choose_backup(config, remote_app)?)
+                };
+                Ok(ApplicationCommand::RetrieveBackup {
+                    backup,
+                    bwlimit,
+                    preview,
+                    dest,
+                })
+            }
+            ApplicationCommandCli::RetrieveFiles {
+                bwlimit,
+                preview,
+                dest,
+            } => Ok(ApplicationCommand::RetrieveFiles {
+                bwlimit,
+                preview,
+                dest,
+            }),
             ApplicationCommandCli::SshSession => Ok(ApplicationCommand::SshSession),
+            ApplicationCommandCli::List { .. } => bail!("list does not operate on a single app"),
+            ApplicationCommandCli::Ps => Ok(ApplicationCommand::Ps),
             ApplicationCommandCli::Tunnel {
                 container_name,
                 host_port,
+                auto_port,
                 remote_port,
+                via_published,
+                background,
             } => {
+                let defaults = config.app_defaults_for(remote_app);
+                let cached_container = load_servers_cache(config)
+                    .find_folder(config, remote_app)
+                    .and_then(|folder| folder.container.to_owned());
                 let container: String = if let Some(container_name) = container_name {
                     container_name
+                } else if let Some(container_name) =
+                    defaults.and_then(|defaults| defaults.container_name.to_owned())
+                {
+                    container_name
                 } else {
-                    let containers: Vec<String> = remote_app.fetch_containers()?;
-                    run_fzf(&containers, "Choose a container", "")?
-                        .ok_or_else(|| anyhow!("Could not find a container"))?
+                    choose_container(
+                        config,
+                        remote_app,
+                        false,
+                        include_stopped,
+                        cached_container.as_deref(),
+                    )?
+                    .ok_or_else(|| anyhow!("Could not find a container"))?
                 };
-                let remote_port = match remote_port {
-                    Some(port) => port.to_owned(),
+                let remote_port = match remote_port
+                    .or_else(|| defaults.and_then(|defaults| defaults.remote_port))
+                {
+                    Some(port) => port,
                     // TODO make better message?
-                    None => prompt_number("Choose a port on the container")?,
+                    None => prompt_port("Choose a port on the container")?,
                 };
                 let host_port = match host_port {
                     Some(port) => port.to_owned(),
-                    None => prompt_number("What local port to use?")?,
+                    None if auto_port => find_free_port()?,
+                    None => match prompt_optional_port(
+                        "What local port to use?",
+                        "Leave empty to pick a free port",
+                    )? {
+                        Some(port) => port,
+                        None => find_free_port()?,
+                    },
                 };
+                if host_port < 1024
+                    && !prompt_confirm(&format!(
+                        "Local port {host_port} is privileged and needs root to bind, continue?"
+                    ))?
+                {
+                    bail!("Cancelled, pick a local port of 1024 or higher");
+                }
                 Ok(ApplicationCommand::Tunnel {
                     container_name: container,
                     remote_port,
                     host_port,
+                    via_published,
+                    background,
+                })
+            }
+            ApplicationCommandCli::Logs {
+                container_name,
+                follow,
+                tail,
+            } => {
+                let container_name = match container_name {
+                    Some(container_name) => Some(container_name),
+                    None => choose_container(config, remote_app, true, include_stopped, None)?,
+                };
+                Ok(ApplicationCommand::Logs {
+                    container_name,
+                    follow,
+                    tail,
+                })
+            }
+            ApplicationCommandCli::Restart {
+                container_name,
+                yes,
+            } => {
+                let container_name = match container_name {
+                    Some(container_name) => Some(container_name),
+                    None => choose_container(config, remote_app, true, include_stopped, None)?,
+                };
+                Ok(ApplicationCommand::Restart {
+                    container_name,
+                    yes,
+                })
+            }
+            ApplicationCommandCli::Exec {
+                container_name,
+                command,
+            } => {
+                let container_name = match container_name {
+                    Some(container_name) => container_name,
+                    None => choose_container(config, remote_app, false, include_stopped, None)?
+                        .ok_or_else(|| anyhow!("Could not find a container"))?,
+                };
+                Ok(ApplicationCommand::Exec {
+                    container_name,
+                    command,
                 })
             }
+            ApplicationCommandCli::Run { command } => {
+                let command = if command.is_empty() {
+                    let command = prompt_string(
+                        "Command to run in the app's directory",
+                        "e.g. docker compose top",
+                    )?;
+                    if command.is_empty() {
+                        bail!("No command given");
+                    }
+                    vec![command]
+                } else {
+                    command
+                };
+                Ok(ApplicationCommand::Run { command })
+            }
+            ApplicationCommandCli::Env { service, set, yes } => {
+                let set = set
+                    .iter()
+                    .map(|var| {
+                        let (key, value) = var
+                            .split_once('=')
+                            .ok_or_else(|| anyhow!("Invalid --set '{var}': expected KEY=VALUE"))?;
+                        if key.is_empty() || key.contains(char::is_whitespace) {
+                            bail!("Invalid variable name '{key}' in --set '{var}'");
+                        }
+                        Ok((key.to_owned(), value.to_owned()))
+                    })
+                    .collect::<Result<_>>()?;
+                Ok(ApplicationCommand::Env { service, set, yes })
+            }
+        }
+    }
+}
+
+const ALL_CONTAINERS: &str = "(all)";
+
+/// Lets the user pick one of the app's containers, `None` means all containers were picked
+fn choose_container(
+    config: &Config,
+    remote_app: &RemoteApp,
+    offer_all: bool,
+    include_stopped: bool,
+    preferred: Option<&str>,
+) -> Result<Option<String>> {
+    let dim = if colors_enabled() {
+        Style::new().dimmed()
+    } else {
+        Style::new()
+    };
+    let mut fetched = remote_app.fetch_containers(config, include_stopped)?;
+    // Listed first so it is the one that is highlighted, the sort keeps the order of the others
+    if let Some(preferred) = preferred {
+        fetched.sort_by_key(|container| container.name != preferred);
+    }
+    let mut containers: Vec<String> = fetched
+        .iter()
+        .map(|container| {
+            if container.is_running() {
+                container.name.to_owned()
+            } else {
+                format!(
+                    "{} {}",
+                    container.name,
+                    dim.paint(format!("({})", container.state))
+                )
+            }
+        })
+        .collect();
+    if containers.is_empty() {
+        if include_stopped {
+            bail!("{} has no containers", remote_app.app_name);
         }
+        bail!(
+            "{} has no running containers, pass --include-stopped to also list stopped ones",
+            remote_app.app_name
+        );
+    }
+    if offer_all {
+        containers.insert(0, ALL_CONTAINERS.to_owned());
+    }
+
+    let header = format!(
+        "Containers of {} on {}",
+        remote_app.app_name, remote_app.host
+    );
+    let selected = run_fzf(&containers, "Choose a container", Some(&header), "", None)?
+        .ok_or_else(|| anyhow!("Could not find a container"))?;
+
+    if selected == ALL_CONTAINERS {
+        return Ok(None);
+    }
+    // Drop the state of stopped containers that is only there for display
+    let selected = strip_ansi(&selected);
+    match selected.split_once(' ') {
+        Some((name, _)) => Ok(Some(name.to_owned())),
+        None => Ok(Some(selected)),
+    }
+}
+
+fn choose_backup(config: &Config, remote_app: &RemoteApp) -> Result<String> {
+    let backups = remote_app.fetch_backups(config)?;
+    if backups.is_empty() {
+        bail!("No backups found for {}", remote_app.app_name);
     }
+    let header = format!(
+        "Backups of {} on {}, newest first",
+        remote_app.app_name, remote_app.host
+    );
+    run_fzf(&backups, "Choose a backup", Some(&header), "", None)?
+        .ok_or_else(|| anyhow!("No backup selected"))
 }
 
 fn project_dirs() -> ProjectDirs {
@@ -141,412 +611,2961 @@ fn project_dirs() -> ProjectDirs {
 }
 
 fn get_env(doc: &Value, service: &str, key: &str) -> Option<String> {
-    let services = doc.get("services")?;
-    let svc = services.get(service)?;
-    let env = svc.get("environment")?;
+    service_env(doc, service)
+        .into_iter()
+        .find_map(|(k, v)| if k == key { Some(v) } else { None })
+}
+
+/// Environment of a compose service, in both the mapping and the `KEY=value` list notation
+fn service_env(doc: &Value, service: &str) -> Vec<(String, String)> {
+    let env = doc
+        .get("services")
+        .and_then(|services| services.get(service))
+        .and_then(|svc| svc.get("environment"));
 
     match env {
-        Value::Mapping(map) => map
-            .get(&Value::String(key.into()))
-            .and_then(|v| v.as_str())
-            .map(|s| s.to_string()),
-        Value::Sequence(seq) => seq.iter().filter_map(|v| v.as_str()).find_map(|entry| {
-            let (k, v) = entry.split_once('=')?;
-            if k == key { Some(v.to_string()) } else { None }
-        }),
-        _ => None,
+        Some(Value::Mapping(map)) => map
+            .iter()
+            .filter_map(|(k, v)| {
+                let value = match v {
+                    Value::String(s) => s.to_owned(),
+                    Value::Number(n) => n.to_string(),
+                    Value::Bool(b) => b.to_string(),
+                    _ => String::new(),
+                };
+                Some((k.as_str()?.to_owned(), value))
+            })
+            .collect(),
+        Some(Value::Sequence(seq)) => seq
+            .iter()
+            .filter_map(|v| v.as_str())
+            .map(|entry| match entry.split_once('=') {
+                Some((k, v)) => (k.to_owned(), v.to_owned()),
+                None => (entry.to_owned(), String::new()),
+            })
+            .collect(),
+        _ => Vec::new(),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Config {
-    pub cache_dir: PathBuf,
-    pub ignore_hosts: Vec<String>,
-    pub next_time_use_msg: bool,
-}
-
-fn build_fzf_lines(cache: &ServersCache) -> Vec<String> {
-    let dim = Style::new().dimmed();
+const HOST_ENV_KEYS: [&str; 2] = ["LETSENCRYPT_HOST", "VIRTUAL_HOST"];
 
-    let mut lines = Vec::new();
+/// Domains configured on the `candidates` services, in the order of that list.
+/// When none of them has a domain every other service is scanned.
+fn hosted_domains(doc: &Value, candidates: &[String]) -> Vec<String> {
+    let Some(services) = doc.get("services").and_then(|s| s.as_mapping()) else {
+        return Vec::new();
+    };
+    let (mut preferred, others): (Vec<&str>, Vec<&str>) = services
+        .keys()
+        .filter_map(|k| k.as_str())
+        .partition(|name| candidates.iter().any(|c| c == name));
+    preferred.sort_by_key(|name| candidates.iter().position(|c| c == name));
 
-    for (host, server) in &cache.servers {
-        for folder in &server.data_folders {
-            let line = format!("{}:{}", folder.path, dim.paint(host));
-            lines.push(line);
-        }
+    let domains = service_domains(doc, &preferred);
+    if domains.is_empty() {
+        service_domains(doc, &others)
+    } else {
+        domains
     }
-
-    lines
 }
 
-fn parse_selection(selected: &str) -> Option<RemoteApp> {
-    let clean = strip_ansi(selected);
+fn hosted_urls(config: &Config, compose_yaml: &str) -> Result<Vec<String>> {
+    let doc: Value = serde_yaml::from_str(compose_yaml)?;
+    Ok(hosted_domains(&doc, &config.url_services)
+        .iter()
+        .map(|domain| format!("https://{domain}"))
+        .collect())
+}
 
-    RemoteApp::from_str(&clean).ok()
+fn fetch_hosted_urls(config: &Config, remote_app: &RemoteApp) -> Result<Vec<String>> {
+    let output = remote_app
+        .docker_config_command(config)
+        .logged_output()
+        .map_err(tool_error("ssh"))?;
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} ({})", stderr.trim(), output.status);
+    }
+    hosted_urls(config, &String::from_utf8_lossy(&output.stdout))
 }
 
-pub fn servers_list(ignore_hosts: Vec<String>) -> anyhow::Result<()> {
-    let cache = load_or_fetch_servers_cache(&ignore_hosts)?;
+/// `hosted-url --all-hosts`, fetches the compose config of `index_concurrency` apps at a time.
+/// Apps without a URL are left out, apps whose config can not be fetched only get a warning.
+fn all_hosted_urls(
+    config: &Config,
+    no_cache: bool,
+    host: Option<&str>,
+    output: OutputFormat,
+) -> Result<()> {
+    let cache = servers_cache(config, no_cache)?;
+    let apps: Vec<RemoteApp> = cache
+        .servers
+        .iter()
+        .filter(|(h, _)| host.is_none_or(|host| host == h.as_str()))
+        .flat_map(|(h, server)| {
+            server.data_folders.iter().map(move |folder| {
+                let app = folder.display_name(config.default_data_root(h));
+                RemoteApp::from_qualified_name(h, None, &app)
+            })
+        })
+        .collect();
 
-    let lines = build_fzf_lines(&cache);
+    let results = for_each_concurrently(
+        &apps,
+        config.index_concurrency,
+        "Fetching apps",
+        |remote_app| fetch_hosted_urls(config, remote_app),
+    );
 
-    if lines.is_empty() {
-        println!("No remote applications found");
-        return Ok(());
+    let mut results: Vec<(&RemoteApp, Result<Vec<String>>)> = apps.iter().zip(results).collect();
+    results.sort_by_cached_key(|(remote_app, _)| {
+        (remote_app.host.to_owned(), remote_app.qualified_name())
+    });
+    let mut hosted = Vec::new();
+    for (remote_app, urls) in results {
+        match urls {
+            Ok(urls) if !urls.is_empty() => hosted.push((remote_app, urls)),
+            Ok(_) => {}
+            Err(e) => eprintln!(
+                "Warning: could not fetch the compose config of {}:{}: {e}",
+                remote_app.qualified_name(),
+                remote_app.host
+            ),
+        }
     }
 
-    lines.iter().for_each(|x| println!("{}", x));
+    if output == OutputFormat::Json {
+        let apps: Vec<serde_json::Value> = hosted
+            .iter()
+            .map(|(remote_app, urls)| {
+                serde_json::json!({
+                    "host": remote_app.host,
+                    "app_name": remote_app.qualified_name(),
+                    "urls": urls,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&apps)?);
+    } else if hosted.is_empty() {
+        eprintln!("No hosted URLs found");
+    } else {
+        let rows: Vec<Vec<String>> = hosted
+            .iter()
+            .flat_map(|(remote_app, urls)| {
+                urls.iter().map(|url| {
+                    vec![
+                        remote_app.host.to_owned(),
+                        remote_app.qualified_name(),
+                        url.to_owned(),
+                    ]
+                })
+            })
+            .collect();
+        print_table(&["HOST", "APP", "URL"], &rows);
+    }
 
     Ok(())
 }
 
-pub fn prompt_remote_app(
-    config: &Config,
-    fzf_search_query: &str,
-) -> anyhow::Result<Option<RemoteApp>> {
-    let ignore_hosts = &config.ignore_hosts;
-    let cache = load_or_fetch_servers_cache(&ignore_hosts)?;
-
-    let lines = build_fzf_lines(&cache);
-
-    if lines.is_empty() {
-        println!("No folders found");
-        return Ok(None);
+fn service_domains(doc: &Value, services: &[&str]) -> Vec<String> {
+    let mut domains: Vec<String> = Vec::new();
+    for service in services {
+        for key in HOST_ENV_KEYS {
+            let Some(value) = get_env(doc, service, key) else {
+                continue;
+            };
+            for domain in value.split(',').map(str::trim) {
+                if !domain.is_empty() && !domains.iter().any(|d| d == domain) {
+                    domains.push(domain.to_owned());
+                }
+            }
+        }
     }
 
-    if let Some(selected) = run_fzf(&lines, "Choose an application", fzf_search_query)? {
-        return Ok(parse_selection(&selected));
-    }
+    domains
+}
 
-    Ok(None)
+#[derive(Debug, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub cache_dir: PathBuf,
+    pub ignore_hosts: Vec<String>,
+    pub only_hosts: Vec<String>,
+    pub next_time_use_msg: bool,
+    pub cache_ttl_seconds: u64,
+    pub container_cache_ttl_seconds: u64,
+    pub index_concurrency: usize,
+    pub connect_timeout_seconds: u64,
+    /// Passed as `-o <option>` to every ssh invocation, e.g. `ProxyJump=bastion`
+    pub ssh_options: Vec<String>,
+    /// Passed as `-i` to every ssh invocation, a leading `~/` is expanded
+    pub identity_file: Option<PathBuf>,
+    pub ssh_retries: u32,
+    pub prefer_builtin_selector: bool,
+    #[serde(deserialize_with = "one_or_many")]
+    pub data_root: Vec<String>,
+    pub rsync_bwlimit: Option<u32>,
+    /// Per host overrides of `data_root`, keyed by ssh alias
+    #[serde(deserialize_with = "map_of_one_or_many")]
+    pub data_roots: BTreeMap<String, Vec<String>>,
+    /// Keyed by `host:app`, see `app_defaults_key`
+    pub app_defaults: BTreeMap<String, AppDefaults>,
+    /// Image prefixes that mark a local compose project as a semantic.works app
+    pub app_images: Vec<String>,
+    /// Services `hosted-url` looks for `LETSENCRYPT_HOST` and `VIRTUAL_HOST` first
+    pub url_services: Vec<String>,
+    /// Folders in the data roots that are never indexed as apps, glob patterns are allowed
+    pub ignore_folders: Vec<String>,
+    /// Only index folders that contain one of `COMPOSE_FILE_NAMES`
+    pub require_compose_file: bool,
+    /// Per host `ssh_options`, keyed by ssh alias and taking precedence over the global ones
+    pub host_ssh_options: BTreeMap<String, Vec<String>>,
+    /// Short names for `--app-name`, expanding to `host:app` or just `app`
+    pub aliases: BTreeMap<String, String>,
 }
 
-fn choose_application_command() -> Result<ApplicationCommandCli> {
-    let options: Vec<String> = ApplicationCommandCli::iter()
-        .map(|c| format!("{}", c))
-        .collect();
+/// Used by `tunnel` when the container or remote port are not passed on the command line
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AppDefaults {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub container_name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub remote_port: Option<u16>,
+}
 
-    let child = Command::new("gum")
-        .args(["choose", "--header", "Select application command"])
-        .args(&options)
-        .stdin(Stdio::piped())
-        .stdout(Stdio::piped())
-        .spawn()?;
+fn app_defaults_key(remote_app: &RemoteApp) -> String {
+    format!("{}:{}", remote_app.host, remote_app.qualified_name())
+}
 
-    let output = child.wait_with_output()?;
+const DEFAULT_DATA_ROOT: &str = "/data";
 
-    if !output.status.success() {
-        bail!("gum was cancelled");
+/// File names `docker compose` looks for in an app's folder
+const COMPOSE_FILE_NAMES: [&str; 4] = [
+    "compose.yaml",
+    "compose.yml",
+    "docker-compose.yaml",
+    "docker-compose.yml",
+];
+
+impl Config {
+    /// Remote directories that hold the apps on `host`, without a trailing slash.
+    /// The first one is used for apps that are not prefixed with their root.
+    pub fn data_roots_for(&self, host: &str) -> Vec<&str> {
+        let roots: Vec<&str> = self
+            .data_roots
+            .get(host)
+            .unwrap_or(&self.data_root)
+            .iter()
+            .map(|root| match root.trim_end_matches('/') {
+                "" => "/",
+                root => root,
+            })
+            .collect();
+        if roots.is_empty() {
+            vec![DEFAULT_DATA_ROOT]
+        } else {
+            roots
+        }
     }
 
-    let selection = String::from_utf8(output.stdout)?.trim().to_owned();
+    pub fn default_data_root(&self, host: &str) -> &str {
+        self.data_roots_for(host)[0]
+    }
 
-    Ok(selection.parse()?)
+    pub fn app_defaults_for(&self, remote_app: &RemoteApp) -> Option<&AppDefaults> {
+        self.app_defaults.get(&app_defaults_key(remote_app))
+    }
 }
 
-fn load_or_fetch_servers_cache(ignore_hosts: &Vec<String>) -> anyhow::Result<ServersCache> {
-    let path = servers_cache_path();
-
-    if path.exists() {
-        Ok(load_servers_cache())
-    } else {
-        let cache = fetch_servers_cache(ignore_hosts)?;
-        write_servers_cache(&cache)?;
-        Ok(cache)
-    }
+/// Older configs have a single `data_root` string instead of a list
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany {
+    One(String),
+    Many(Vec<String>),
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Self {
-            cache_dir: default_cache_dir(),
-            ignore_hosts: Vec::new(),
-            next_time_use_msg: true,
+impl From<OneOrMany> for Vec<String> {
+    fn from(value: OneOrMany) -> Self {
+        match value {
+            OneOrMany::One(value) => vec![value],
+            OneOrMany::Many(values) => values,
         }
     }
 }
 
-fn default_cache_dir() -> PathBuf {
-    project_dirs().cache_dir().to_path_buf()
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    Ok(OneOrMany::deserialize(deserializer)?.into())
 }
 
-fn config_dir() -> PathBuf {
-    project_dirs().config_dir().to_path_buf()
+fn map_of_one_or_many<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, Vec<String>>, D::Error> {
+    let map = BTreeMap::<String, OneOrMany>::deserialize(deserializer)?;
+    Ok(map.into_iter().map(|(k, v)| (k, v.into())).collect())
 }
 
-fn strip_ansi(s: &str) -> String {
-    let re = Regex::new(r"\x1b\[[0-9;]*m").unwrap();
-    re.replace_all(s, "").to_string()
+static QUIET: AtomicBool = AtomicBool::new(false);
+
+/// Whether decorative output such as tunnel instructions and status messages should be left out
+fn quiet() -> bool {
+    QUIET.load(Ordering::Relaxed)
 }
 
-pub fn load_config() -> Config {
-    let path = config_dir().join("config.toml");
+static QUIET_FLAG: AtomicBool = AtomicBool::new(false);
 
-    if let Ok(contents) = fs::read_to_string(&path) {
-        toml::from_str(&contents).unwrap_or_default()
-    } else {
-        Config::default()
-    }
+/// Whether `--quiet` was passed, unlike `quiet` it stays off when stdout is piped so status on stderr is kept
+fn quiet_flag() -> bool {
+    QUIET_FLAG.load(Ordering::Relaxed)
 }
 
-pub fn fetch_servers_cache(ignore_hosts: &Vec<String>) -> anyhow::Result<ServersCache> {
-    let mut hosts = read_ssh_hosts()?;
-    hosts.retain(|h| !ignore_hosts.contains(h));
-    let mut servers = BTreeMap::new();
+static NO_COLOR: AtomicBool = AtomicBool::new(false);
 
-    for host in hosts {
-        if host.is_empty() {
-            continue;
-        }
-        let bar = create_and_start_spinner(&format!("Indexing apps from {host}..."));
-        let folders = fetch_data_folders(&host);
-        bar.finish();
+fn colors_enabled() -> bool {
+    !NO_COLOR.load(Ordering::Relaxed)
+}
 
-        servers.insert(
-            host,
-            ServerEntry {
-                last_updated: Utc::now().timestamp(),
-                data_folders: folders,
-            },
-        );
+const UNREACHABLE_MARKER: &str = "⚠";
+
+/// Fails with the closest known hosts when `host` is neither in the ssh config nor in the servers cache.
+/// `host` may include a port.
+fn validate_host(config: &Config, host: &str) -> Result<()> {
+    let alias = host.split(':').next().unwrap_or(host);
+    let mut known: Vec<String> = read_ssh_hosts()?.into_iter().map(|h| h.alias).collect();
+    known.extend(load_servers_cache(config).servers.into_keys());
+    known.sort();
+    known.dedup();
+    if known.is_empty() || known.iter().any(|known| known == alias) {
+        return Ok(());
     }
 
-    Ok(ServersCache { servers })
+    let mut close: Vec<(f64, &String)> = known
+        .iter()
+        .map(|known| (strsim::jaro_winkler(alias, known), known))
+        .filter(|(similarity, known)| *similarity >= 0.8 || known.contains(alias))
+        .collect();
+    close.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+
+    let suggestion = match close.as_slice() {
+        [] => ".".to_owned(),
+        close => {
+            let names: Vec<&str> = close.iter().take(3).map(|(_, h)| h.as_str()).collect();
+            format!(", did you mean {}?", names.join(", "))
+        }
+    };
+    bail!(
+        "Unknown host '{alias}', it is not in your ssh config{suggestion} Pass --no-validate-host to use it anyway"
+    )
 }
 
-pub fn write_default_config() -> anyhow::Result<()> {
-    let path = config_dir().join("config.toml");
-    if fs::exists(&path)? {
-        bail!("Config file already exists at: {}", &path.display());
+/// Matches an `--app-name` that is not an indexed folder on its host against the folders that are.
+/// A single candidate is used with a notice, several are offered in the picker.
+/// Apps on hosts that are not in the cache are taken as is.
+fn resolve_app_name(config: &Config, remote_app: RemoteApp) -> Result<RemoteApp> {
+    let cache = load_servers_cache(config);
+    let Some(server) = cache.servers.get(&remote_app.host) else {
+        return Ok(remote_app);
+    };
+    if server.data_folders.is_empty() || cache.find_folder(config, &remote_app).is_some() {
+        return Ok(remote_app);
     }
 
-    fs::create_dir_all(&config_dir())?;
-    let path = config_dir().join("config.toml");
-    let cfg = Config::default();
-    let contents = toml::to_string_pretty(&cfg)?;
-    println!("Written config to {}", path.display());
-    std::fs::write(path, contents)?;
-    Ok(())
-}
+    let default_root = config.default_data_root(&remote_app.host);
+    let names: Vec<String> = server
+        .data_folders
+        .iter()
+        .map(|folder| folder.display_name(default_root))
+        .collect();
+    let wanted = remote_app.qualified_name().to_lowercase();
+    let mut candidates: Vec<&String> = names
+        .iter()
+        .filter(|name| name.to_lowercase().contains(&wanted))
+        .collect();
+    if candidates.is_empty() {
+        // Letters in the same order, `aqa` finds `app-bar-qa`
+        candidates = names
+            .iter()
+            .filter(|name| {
+                let name = name.to_lowercase();
+                let mut letters = name.chars();
+                wanted.chars().all(|c| letters.any(|l| l == c))
+            })
+            .collect();
+    }
 
-pub fn init_runtime_dirs(cfg: &Config) -> anyhow::Result<()> {
-    fs::create_dir_all(&cfg.cache_dir)?;
-    Ok(())
-}
+    let name = match candidates.as_slice() {
+        [] => bail!(
+            "No app named {} on {}, run `rpio apps list --host {}` to see its apps",
+            remote_app.qualified_name(),
+            remote_app.host,
+            remote_app.host
+        ),
+        [name] => {
+            // Acting on another app than the one asked for must not go unnoticed when stdout is piped
+            if !quiet_flag() {
+                eprintln!(
+                    "No app named {} on {}, using {name}",
+                    remote_app.qualified_name(),
+                    remote_app.host
+                );
+            }
+            name.to_string()
+        }
+        _ => {
+            let lines: Vec<String> = candidates.iter().map(|name| name.to_string()).collect();
+            let header = format!(
+                "No app named {} on {}, these apps match it",
+                remote_app.qualified_name(),
+                remote_app.host
+            );
+            run_fzf(&lines, "Choose an application", Some(&header), "", None)?
+                .ok_or_else(|| anyhow!("No app selected"))?
+        }
+    };
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ServersCache {
-    pub servers: BTreeMap<String, ServerEntry>,
+    Ok(RemoteApp::from_qualified_name(
+        &remote_app.host,
+        remote_app.port,
+        &name,
+    ))
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct ServerEntry {
+/// How many of the most used apps are moved to the top of the picker
+const BOOSTED_APPS: usize = 5;
+
+/// Moves the apps that were used most often and most recently to the top, most used first
+fn boost_recently_used(lines: &mut Vec<String>) {
+    let frecencies = app_frecencies();
+    if frecencies.is_empty() {
+        return;
+    }
+
+    let frecency = |line: &String| {
+        let mut fields = line.rsplit('\t');
+        let app = fields.next()?;
+        let host = fields.next()?;
+        frecencies.get(&history_key(host, app)).copied()
+    };
+    let mut used: Vec<(f64, usize)> = lines
+        .iter()
+        .enumerate()
+        .filter_map(|(i, line)| Some((frecency(line)?, i)))
+        .collect();
+    used.sort_by(|(a, _), (b, _)| b.total_cmp(a));
+    used.truncate(BOOSTED_APPS);
+
+    let boosted: Vec<String> = used.iter().map(|(_, i)| lines[*i].to_owned()).collect();
+    let mut i = 0;
+    lines.retain(|_| {
+        i += 1;
+        !used.iter().any(|(_, boosted)| *boosted == i - 1)
+    });
+    lines.splice(0..0, boosted);
+}
+
+/// Keeps track of the used apps for `boost_recently_used`, losing the history is not worth failing for
+fn remember_app_use(remote_app: &RemoteApp, command: &str) {
+    if let Err(e) = record_app_use(remote_app, command) {
+        log::debug!("could not update {}: {e}", history_path().display());
+    }
+}
+
+/// `host_filter` narrows the lines down to hosts whose alias contains it, before fzf gets to see them,
+/// `only_reachable` leaves out the hosts that could not be indexed
+fn build_fzf_lines(
+    config: &Config,
+    cache: &ServersCache,
+    host_filter: Option<&str>,
+    only_reachable: bool,
+    sort: PickerSort,
+) -> Vec<String> {
+    let dim = if colors_enabled() {
+        Style::new().dimmed()
+    } else {
+        Style::new()
+    };
+
+    let mut lines = Vec::new();
+    let mut unreachable = Vec::new();
+
+    // Also covers `--exclude`, invalid patterns were already reported while reading the cache
+    let ignored_hosts =
+        build_glob_set(&config.ignore_hosts).unwrap_or_else(|_| globset::GlobSet::empty());
+    let mut servers: Vec<(&String, &ServerEntry)> = cache
+        .servers
+        .iter()
+        .filter(|(host, _)| !ignored_hosts.is_match(host))
+        .filter(|(host, _)| host_filter.is_none_or(|filter| host.contains(filter)))
+        .filter(|(_, server)| !only_reachable || server.error.is_none())
+        .collect();
+    if sort == PickerSort::Recent {
+        servers.sort_by_key(|(_, server)| std::cmp::Reverse(server.last_updated));
+    }
+    for (host, server) in servers {
+        if let Some(error) = &server.error {
+            let line = format!("{UNREACHABLE_MARKER} {host} unreachable: {error}");
+            let line = dim.paint(line).to_string();
+            // Sorted by app the hosts no longer form groups, so unreachable ones go last
+            if sort == PickerSort::App {
+                unreachable.push(line);
+            } else {
+                lines.push(line);
+            }
+        }
+        for folder in &server.data_folders {
+            let app = folder.display_name(config.default_data_root(host));
+            let mut line = format!("{app}:{}", dim.paint(host));
+            if let Some(host_name) = &server.host_name {
+                line.push_str(&format!(" {}", dim.paint(format!("({host_name})"))));
+            }
+            // Hidden by fzf, unlike the `:` above a tab can not be part of a host or folder name
+            line.push_str(&format!("\t{host}\t{app}"));
+            lines.push(line);
+        }
+    }
+
+    if sort == PickerSort::App {
+        // Sorts on the hidden `app` and `host` fields, the visible part starts with color codes
+        lines.sort_by_cached_key(|line| {
+            let mut fields = line.rsplit('\t');
+            let app = fields.next().unwrap_or_default().to_owned();
+            (app, fields.next().unwrap_or_default().to_owned())
+        });
+    }
+    boost_recently_used(&mut lines);
+    lines.extend(unreachable);
+
+    lines
+}
+
+/// `None` for the line of an unreachable host, which has no app to select
+fn parse_selection(selected: &str) -> Result<Option<RemoteApp>> {
+    let clean = strip_ansi(selected);
+    if clean.starts_with(UNREACHABLE_MARKER) {
+        return Ok(None);
+    }
+
+    // The `app:host` that is shown is only for display, the hidden fields are unambiguous
+    let mut fields = clean.split('\t').skip(1);
+    match (fields.next(), fields.next()) {
+        (Some(host), Some(app)) if !host.is_empty() && !app.is_empty() => {
+            Ok(Some(RemoteApp::from_qualified_name(host, None, app)))
+        }
+        _ => bail!("Unexpected picker line '{clean}': expected '<display>\\t<host>\\t<app>'"),
+    }
+}
+
+pub fn servers_list(
+    config: &Config,
+    no_cache: bool,
+    host: Option<&str>,
+    output: OutputFormat,
+    format: Option<&str>,
+) -> anyhow::Result<()> {
+    if let Some(format) = format {
+        check_list_format(format)?;
+    }
+    let cache = servers_cache(config, no_cache)?;
+    let ignored_hosts = build_glob_set(&config.ignore_hosts)?;
+
+    let apps = cache
+        .servers
+        .iter()
+        .filter(|(h, _)| host.is_none_or(|host| host == h.as_str()))
+        .filter(|(h, _)| !ignored_hosts.is_match(h))
+        .flat_map(|(h, server)| server.data_folders.iter().map(move |folder| (h, folder)));
+
+    // An explicit template wins over --output json
+    if output == OutputFormat::Json && format.is_none() {
+        let apps: Vec<serde_json::Value> = apps
+            .map(|(host, folder)| {
+                serde_json::json!({
+                    "host": host,
+                    "app": folder.path,
+                    "root": folder.root,
+                    "container": folder.container,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&apps)?);
+        return Ok(());
+    }
+
+    let lines: Vec<String> = apps
+        .map(|(host, folder)| {
+            let default_root = config.default_data_root(host);
+            let app = folder.display_name(default_root);
+            match format {
+                Some(format) => render_list_format(format, |placeholder| match placeholder {
+                    "host" => host.to_owned(),
+                    "app" => app.to_owned(),
+                    "name" => folder.path.to_owned(),
+                    "root" => folder.root.to_owned(),
+                    "dir" => folder.directory(),
+                    "container" => folder.container.to_owned().unwrap_or_default(),
+                    _ => String::new(),
+                }),
+                None => format!("{app}:{host}"),
+            }
+        })
+        .collect();
+
+    if lines.is_empty() {
+        eprintln!("No remote applications found");
+        return Ok(());
+    }
+
+    lines.iter().for_each(|x| println!("{}", x));
+
+    Ok(())
+}
+
+const LIST_PLACEHOLDERS: [&str; 6] = ["host", "app", "name", "root", "dir", "container"];
+
+fn list_placeholder_regex() -> Regex {
+    Regex::new(r"\{(\w+)\}").unwrap()
+}
+
+fn check_list_format(format: &str) -> Result<()> {
+    for captures in list_placeholder_regex().captures_iter(format) {
+        let placeholder = &captures[1];
+        if !LIST_PLACEHOLDERS.contains(&placeholder) {
+            bail!(
+                "Unknown placeholder {{{placeholder}}} in --format, expected one of {}",
+                LIST_PLACEHOLDERS.map(|p| format!("{{{p}}}")).join(", ")
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Substitutes the `{placeholder}`s in `format`, `\t` and `\n` can be used for tabs and newlines
+fn render_list_format(format: &str, value: impl Fn(&str) -> String) -> String {
+    let line = list_placeholder_regex()
+        .replace_all(format, |captures: &regex::Captures| value(&captures[1]));
+    line.replace("\\t", "\t").replace("\\n", "\n")
+}
+
+fn servers_cache(config: &Config, no_cache: bool) -> Result<ServersCache> {
+    if no_cache {
+        fetch_servers_cache(config, false)
+    } else {
+        load_or_fetch_servers_cache(config)
+    }
+}
+
+pub fn prompt_remote_app(
+    config: &Config,
+    fzf_search_query: &str,
+    no_cache: bool,
+    host_filter: Option<&str>,
+    only_reachable: bool,
+    sort: PickerSort,
+) -> anyhow::Result<Option<RemoteApp>> {
+    let cache = servers_cache(config, no_cache)?;
+
+    let lines = build_fzf_lines(config, &cache, host_filter, only_reachable, sort);
+
+    if lines.is_empty() {
+        println!("No folders found");
+        return Ok(None);
+    }
+
+    let preview = preview_command(config);
+    // A failing fzf is an error of its own, only cancelling ends up here
+    let Some(selected) = run_fzf(
+        &lines,
+        "Choose an application",
+        Some("Select an app to operate on, shown as app:host"),
+        fzf_search_query,
+        preview.as_deref(),
+    )?
+    else {
+        bail!("No app selected");
+    };
+
+    parse_selection(&selected)
+}
+
+/// Apps marked in the picker, unreachable hosts are skipped
+fn prompt_remote_apps(
+    config: &Config,
+    fzf_search_query: &str,
+    no_cache: bool,
+    host_filter: Option<&str>,
+    only_reachable: bool,
+    sort: PickerSort,
+) -> Result<Vec<RemoteApp>> {
+    let cache = servers_cache(config, no_cache)?;
+    let lines = build_fzf_lines(config, &cache, host_filter, only_reachable, sort);
+
+    if lines.is_empty() {
+        println!("No folders found");
+        return Ok(Vec::new());
+    }
+
+    let preview = preview_command(config);
+    let selected = run_fzf_multi(
+        &lines,
+        "Choose applications",
+        Some("Mark the apps to operate on with tab, confirm with enter"),
+        fzf_search_query,
+        preview.as_deref(),
+    )?;
+
+    let remote_apps: Vec<Option<RemoteApp>> = selected
+        .iter()
+        .map(|line| parse_selection(line))
+        .collect::<Result<_>>()?;
+
+    Ok(remote_apps.into_iter().flatten().collect())
+}
+
+/// fzf runs this for the highlighted line, it calls back into `rpio preview` with the same config, cache folder and identity file
+fn preview_command(config: &Config) -> Option<String> {
+    let exe = std::env::current_exe().ok()?;
+    let mut command = quote(&exe.to_string_lossy());
+    if let Some(path) = CONFIG_PATH.get() {
+        command.push_str(&format!(" --config {}", quote(&path.to_string_lossy())));
+    }
+    if let Some(path) = CACHE_DIR.get() {
+        command.push_str(&format!(" --cache-dir {}", quote(&path.to_string_lossy())));
+    }
+    if let Some(path) = &config.identity_file {
+        command.push_str(&format!(" --identity {}", quote(&path.to_string_lossy())));
+    }
+    command.push_str(" preview {}");
+    Some(command)
+}
+
+fn preview_cache_path(config: &Config, remote_app: &RemoteApp) -> PathBuf {
+    let directory = remote_app.remote_directory(config).replace('/', "%");
+    previews_cache_dir().join(format!("{}{directory}.yml", remote_app.host))
+}
+
+/// Undecorated output of `__complete`, only reads the cache so it stays fast enough for completion
+fn print_completion_candidates(config: &Config, target: CompleteTarget) {
+    let cache = load_servers_cache(config);
+    for (host, server) in &cache.servers {
+        match target {
+            CompleteTarget::Hosts => println!("{host}"),
+            CompleteTarget::Apps => {
+                for folder in &server.data_folders {
+                    let app = folder.display_name(config.default_data_root(host));
+                    println!("{host}:{app}");
+                }
+            }
+        }
+    }
+}
+
+/// Prints the compose config of the app on a picker line, reusing it for `cache_ttl_seconds`
+/// so scrolling through the picker does not open a new ssh connection every time
+fn print_app_preview(config: &Config, line: &str) -> Result<()> {
+    let Some(remote_app) = parse_selection(line)? else {
+        return Ok(());
+    };
+    let path = preview_cache_path(config, &remote_app);
+
+    let is_fresh = fs::metadata(&path)
+        .and_then(|metadata| metadata.modified())
+        .is_ok_and(|modified| {
+            modified
+                .elapsed()
+                .is_ok_and(|age| age.as_secs() < config.cache_ttl_seconds)
+        });
+    if is_fresh {
+        print!("{}", fs::read_to_string(&path)?);
+        return Ok(());
+    }
+
+    let output = remote_app
+        .docker_config_command(config)
+        .logged_output()
+        .map_err(tool_error("ssh"))?;
+    if !output.status.success() {
+        bail!(
+            "Could not fetch the compose config: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+
+    let yaml = String::from_utf8_lossy(&output.stdout);
+    // fzf kills previews while scrolling, a half written file would be served for the whole ttl
+    write_atomically(&path, &yaml)?;
+    print!("{yaml}");
+
+    Ok(())
+}
+
+/// Runs the entered command through the container's shell, an empty command opens a shell
+fn prompt_exec_command() -> Result<Vec<String>> {
+    let command = prompt_string(
+        "Command to run inside the container",
+        "Leave empty to open a shell",
+    )?;
+    if command.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    Ok(vec!["/bin/sh".to_owned(), "-c".to_owned(), command])
+}
+
+/// `previous` is highlighted when it is still one of the commands
+fn choose_application_command(previous: Option<&str>) -> Result<ApplicationCommandCli> {
+    let options: Vec<String> = ApplicationCommandCli::iter()
+        .map(|c| format!("{}", c))
+        .collect();
+
+    let mut command = Command::new("gum");
+    command.args(["choose", "--header", "Select application command"]);
+    if let Some(previous) = previous.filter(|p| options.iter().any(|o| o == p)) {
+        command.args(["--selected", previous]);
+    }
+    let child = command
+        .args(&options)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .logged_spawn()
+        .map_err(tool_error("gum"))?;
+
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        bail!("gum was cancelled");
+    }
+
+    let selection = String::from_utf8(output.stdout)?.trim().to_owned();
+
+    Ok(selection.parse()?)
+}
+
+fn load_or_fetch_servers_cache(config: &Config) -> anyhow::Result<ServersCache> {
+    ensure_servers_cache_not_newer()?;
+    let cache = read_servers_cache(config).unwrap_or_else(|e| {
+        eprintln!("Warning: {e}, re-indexing all hosts");
+        None
+    });
+    let Some(mut cache) = cache else {
+        let cache = fetch_servers_cache(config, false)?;
+        write_servers_cache(&cache)?;
+        return Ok(cache);
+    };
+
+    // Hosts that were removed from the ssh config or are ignored now are left out, new ones are indexed.
+    // They are only dropped from the file once it is written anyway, `--exclude` is for one run.
+    let hosts = configured_hosts(config)?;
+    cache
+        .servers
+        .retain(|host, _| hosts.iter().any(|h| &h.alias == host));
+
+    let now = Utc::now().timestamp();
+    let expired_hosts: Vec<(String, Option<String>)> = hosts
+        .into_iter()
+        .filter(|host| {
+            cache
+                .servers
+                .get(&host.alias)
+                .is_none_or(|entry| entry.is_expired(config.cache_ttl_seconds, now))
+        })
+        .map(|host| {
+            let host_name = host.resolved_name();
+            (host.alias, host_name)
+        })
+        .collect();
+
+    if expired_hosts.is_empty() {
+        return Ok(cache);
+    }
+
+    let servers = index_hosts(config, expired_hosts, false);
+    cache.servers.extend(servers);
+    write_servers_cache(&cache)?;
+
+    Ok(cache)
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            cache_dir: default_cache_dir(),
+            ignore_hosts: Vec::new(),
+            only_hosts: Vec::new(),
+            next_time_use_msg: true,
+            cache_ttl_seconds: 86400,
+            container_cache_ttl_seconds: 60,
+            index_concurrency: 8,
+            connect_timeout_seconds: 30,
+            ssh_options: Vec::new(),
+            identity_file: None,
+            ssh_retries: 2,
+            prefer_builtin_selector: false,
+            data_root: vec![DEFAULT_DATA_ROOT.to_owned()],
+            rsync_bwlimit: None,
+            data_roots: BTreeMap::new(),
+            app_defaults: BTreeMap::new(),
+            app_images: vec!["semtech/mu-identifier".to_owned()],
+            url_services: ["identifier", "frontend", "nginx", "dispatcher"]
+                .map(str::to_owned)
+                .to_vec(),
+            host_ssh_options: BTreeMap::new(),
+            aliases: BTreeMap::new(),
+            ignore_folders: vec!["lost+found".to_owned()],
+            require_compose_file: false,
+        }
+    }
+}
+
+fn default_cache_dir() -> PathBuf {
+    project_dirs().cache_dir().to_path_buf()
+}
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Set from `cache_dir` in the config, or `--cache-dir` when it is passed
+fn set_cache_dir(path: PathBuf) {
+    CACHE_DIR.set(path).ok();
+}
+
+/// Folder of the servers cache and every other cache file
+pub fn cache_dir() -> PathBuf {
+    match CACHE_DIR.get() {
+        Some(path) => path.to_owned(),
+        None => default_cache_dir(),
+    }
+}
+
+/// Writes through a temporary file in the same folder, so a reader never sees a half written file
+pub fn write_atomically(path: &Path, contents: &str) -> Result<()> {
+    let folder = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(folder)?;
+
+    let mut tmp = NamedTempFile::new_in(folder)?;
+    tmp.write_all(contents.as_bytes())?;
+    tmp.flush()?;
+    tmp.persist(path)?;
+
+    Ok(())
+}
+
+fn config_dir() -> PathBuf {
+    project_dirs().config_dir().to_path_buf()
+}
+
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+
+/// Makes `config_file_path` return `path` instead of the default location
+fn override_config_path(path: PathBuf) {
+    CONFIG_PATH.set(path).ok();
+}
+
+fn config_file_path() -> PathBuf {
+    match CONFIG_PATH.get() {
+        Some(path) => path.to_owned(),
+        None => config_dir().join("config.toml"),
+    }
+}
+
+/// Removes CSI sequences (colors, cursor movement, ...), OSC sequences (titles, hyperlinks)
+/// and the remaining escapes such as charset selection
+fn strip_ansi(s: &str) -> String {
+    let re =
+        Regex::new(r"\x1b\[[0-?]*[ -/]*[@-~]|\x1b\][^\x07\x1b]*(?:\x07|\x1b\\)|\x1b[ -/]*[0-~]")
+            .unwrap();
+    re.replace_all(s, "").to_string()
+}
+
+pub fn load_config(path: &Path) -> Config {
+    if let Ok(contents) = fs::read_to_string(path) {
+        toml::from_str(&contents).unwrap_or_default()
+    } else {
+        Config::default()
+    }
+}
+
+/// Hosts from the ssh config that are not filtered out by `only_hosts` or `ignore_hosts`
+fn configured_hosts(config: &Config) -> Result<Vec<SshHost>> {
+    let only_hosts = build_glob_set(&config.only_hosts)?;
+    let ignored_hosts = build_glob_set(&config.ignore_hosts)?;
+    let mut hosts = read_ssh_hosts()?;
+    // The allowlist is applied first, `ignore_hosts` can still exclude hosts it matched
+    hosts.retain(|h| {
+        !h.alias.is_empty()
+            && (config.only_hosts.is_empty() || only_hosts.is_match(&h.alias))
+            && !ignored_hosts.is_match(&h.alias)
+    });
+    Ok(hosts)
+}
+
+pub fn fetch_servers_cache(config: &Config, deep: bool) -> anyhow::Result<ServersCache> {
+    let hosts = configured_hosts(config)?
+        .into_iter()
+        .map(|host| {
+            let host_name = host.resolved_name();
+            (host.alias, host_name)
+        })
+        .collect();
+    let servers = index_hosts(config, hosts, deep);
+    let cache = ServersCache::new(servers);
+    if !quiet_flag() {
+        eprintln!("{}", cache.summary());
+    }
+
+    Ok(cache)
+}
+
+/// Re-indexes `host` and merges it into the cache, without a cache yet every host is indexed
+fn refresh_single_host(config: &Config, host: &str, deep: bool) -> Result<()> {
+    let Some(ssh_host) = configured_hosts(config)?
+        .into_iter()
+        .find(|h| h.alias == host)
+    else {
+        if read_ssh_hosts()?.iter().any(|h| h.alias == host) {
+            bail!("Host '{host}' is skipped because of ignore_hosts or only_hosts");
+        }
+        bail!("Unknown host '{host}', it is not in your ssh config");
+    };
+
+    ensure_servers_cache_not_newer()?;
+    let cache = read_servers_cache(config).unwrap_or_else(|e| {
+        eprintln!("Warning: {e}, re-indexing all hosts");
+        None
+    });
+    let Some(mut cache) = cache else {
+        return write_servers_cache(&fetch_servers_cache(config, deep)?);
+    };
+
+    let host_name = ssh_host.resolved_name();
+    let servers = index_hosts(config, vec![(ssh_host.alias, host_name)], deep);
+    cache.servers.extend(servers);
+    write_servers_cache(&cache)
+}
+
+/// Indexes the given `(host, host_name)` pairs using at most `index_concurrency` ssh connections at once,
+/// a `deep` index also looks up the primary container of every app
+fn index_hosts(
+    config: &Config,
+    hosts: Vec<(String, Option<String>)>,
+    deep: bool,
+) -> BTreeMap<String, ServerEntry> {
+    let entries = for_each_concurrently(
+        &hosts,
+        config.index_concurrency,
+        "Indexing hosts",
+        |(host, host_name)| {
+            let data_folders = fetch_data_folders(config, host).and_then(|folders| {
+                if deep {
+                    fetch_primary_containers(config, host, folders)
+                } else {
+                    Ok(folders)
+                }
+            });
+            ServerEntry {
+                last_updated: Utc::now().timestamp(),
+                host_name: host_name.to_owned(),
+                error: data_folders.as_ref().err().map(|e| e.to_string()),
+                data_folders: data_folders.unwrap_or_default(),
+            }
+        },
+    );
+
+    hosts
+        .into_iter()
+        .map(|(host, _)| host)
+        .zip(entries)
+        .collect()
+}
+
+/// Runs `f` on every item, `limit` at a time, while a spinner counts the finished ones.
+/// The results are in the order of `items`.
+fn for_each_concurrently<T: Sync, R: Send>(
+    items: &[T],
+    limit: usize,
+    label: &str,
+    f: impl Fn(&T) -> R + Sync,
+) -> Vec<R> {
+    let total = items.len();
+    let next = AtomicUsize::new(0);
+    let results = Mutex::new((0, (0..total).map(|_| None).collect::<Vec<Option<R>>>()));
+    let bar = create_and_start_timed_spinner(&format!("{label} 0/{total}..."));
+
+    thread::scope(|scope| {
+        for _ in 0..limit.clamp(1, total.max(1)) {
+            scope.spawn(|| {
+                loop {
+                    let index = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(item) = items.get(index) else {
+                        break;
+                    };
+                    let result = f(item);
+
+                    let mut results = results.lock().unwrap();
+                    results.0 += 1;
+                    results.1[index] = Some(result);
+                    bar.set_message(format!("{label} {}/{total}...", results.0));
+                }
+            });
+        }
+    });
+    bar.finish_and_clear();
+
+    let (_, results) = results.into_inner().unwrap();
+    results.into_iter().flatten().collect()
+}
+
+struct HostCheck {
+    host: String,
+    latency: Duration,
+    error: Option<String>,
+}
+
+/// Runs `ssh <host> true` against every configured host, `index_concurrency` at a time
+fn check_hosts(config: &Config, output: OutputFormat) -> Result<()> {
+    let hosts = configured_hosts(config)?;
+    let mut checks =
+        for_each_concurrently(&hosts, config.index_concurrency, "Checking hosts", |host| {
+            let started = Instant::now();
+            let result = ssh_command(config, &host.alias, None)
+                .arg("true")
+                .logged_output()
+                .map_err(tool_error("ssh"));
+            let error = match result {
+                Ok(output) if output.status.success() => None,
+                Ok(output) => {
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    Some(format!("{} ({})", stderr.trim(), output.status))
+                }
+                Err(e) => Some(e.to_string()),
+            };
+
+            HostCheck {
+                host: host.alias.to_owned(),
+                latency: started.elapsed(),
+                error,
+            }
+        });
+    checks.sort_by(|a, b| a.host.cmp(&b.host));
+
+    if output == OutputFormat::Json {
+        let hosts: Vec<serde_json::Value> = checks
+            .iter()
+            .map(|check| {
+                serde_json::json!({
+                    "host": check.host,
+                    "reachable": check.error.is_none(),
+                    "latency_ms": check.latency.as_millis(),
+                    "error": check.error,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&hosts)?);
+    } else if checks.is_empty() {
+        eprintln!("No hosts found in your ssh config");
+    } else {
+        let rows: Vec<Vec<String>> = checks
+            .iter()
+            .map(|check| {
+                vec![
+                    check.host.to_owned(),
+                    match check.error {
+                        None => "reachable".to_owned(),
+                        Some(_) => "unreachable".to_owned(),
+                    },
+                    format!("{}ms", check.latency.as_millis()),
+                    check.error.to_owned().unwrap_or_default(),
+                ]
+            })
+            .collect();
+        print_table(&["HOST", "STATUS", "LATENCY", "ERROR"], &rows);
+    }
+
+    let unreachable = checks.iter().filter(|check| check.error.is_some()).count();
+    if unreachable > 0 {
+        bail!("{unreachable} of {} hosts are unreachable", hosts.len());
+    }
+
+    Ok(())
+}
+
+struct DoctorCheck {
+    name: String,
+    ok: bool,
+    /// A failed critical check makes `doctor` exit with a non-zero status
+    critical: bool,
+    detail: String,
+}
+
+/// Tools rpio shells out to, `false` for the ones it can do without
+const DOCTOR_TOOLS: [(&str, bool, &str); 4] = [
+    ("ssh", true, ""),
+    ("gum", true, ""),
+    ("fzf", false, "the builtin selector is used instead"),
+    (
+        "rsync",
+        false,
+        "only needed by retrieve-backup and retrieve-files",
+    ),
+];
+
+fn dir_writable(dir: &Path) -> Result<()> {
+    fs::create_dir_all(dir)?;
+    NamedTempFile::new_in(dir)?;
+    Ok(())
+}
+
+fn doctor_checks() -> Vec<DoctorCheck> {
+    let mut checks = Vec::new();
+
+    for (program, critical, fallback) in DOCTOR_TOOLS {
+        let (ok, detail) = match tool_version(program) {
+            Ok(version) => (true, version),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound && fallback.is_empty() => {
+                (false, format!("not found, {}", install_hint(program)))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (
+                false,
+                format!("not found, {fallback}; {}", install_hint(program)),
+            ),
+            Err(e) => (false, e.to_string()),
+        };
+        checks.push(DoctorCheck {
+            name: program.to_owned(),
+            ok,
+            critical,
+            detail,
+        });
+    }
+
+    let (ok, detail) = match ssh_config_path() {
+        Ok(path) if !path.exists() => (false, format!("{} does not exist", path.display())),
+        Ok(path) => match read_ssh_hosts() {
+            Ok(hosts) => (
+                true,
+                format!("{} with {} hosts", path.display(), hosts.len()),
+            ),
+            Err(e) => (false, e.to_string()),
+        },
+        Err(e) => (false, e.to_string()),
+    };
+    checks.push(DoctorCheck {
+        name: "ssh config".to_owned(),
+        ok,
+        critical: true,
+        detail,
+    });
+
+    let config_file = config_file_path();
+    let dirs = [
+        ("config dir", config_file.parent().map(Path::to_path_buf)),
+        ("cache dir", Some(cache_dir())),
+    ];
+    for (name, dir) in dirs {
+        let Some(dir) = dir else {
+            continue;
+        };
+        let (ok, detail) = match dir_writable(&dir) {
+            Ok(()) => (true, format!("{} is writable", dir.display())),
+            Err(e) => (false, format!("{} is not writable: {e}", dir.display())),
+        };
+        checks.push(DoctorCheck {
+            name: name.to_owned(),
+            ok,
+            critical: true,
+            detail,
+        });
+    }
+
+    checks
+}
+
+/// Checks the tools, ssh config and folders rpio depends on
+fn doctor(output: OutputFormat) -> Result<()> {
+    let checks = doctor_checks();
+
+    if output == OutputFormat::Json {
+        let checks: Vec<serde_json::Value> = checks
+            .iter()
+            .map(|check| {
+                serde_json::json!({
+                    "check": check.name,
+                    "ok": check.ok,
+                    "critical": check.critical,
+                    "detail": check.detail,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&checks)?);
+    } else {
+        for check in &checks {
+            let marker = match (check.ok, check.critical) {
+                (true, _) => "✔",
+                (false, true) => "✘",
+                (false, false) => UNREACHABLE_MARKER,
+            };
+            println!("{marker} {}: {}", check.name, check.detail);
+        }
+    }
+
+    let failed = checks
+        .iter()
+        .filter(|check| check.critical && !check.ok)
+        .count();
+    if failed > 0 {
+        bail!("{failed} critical checks failed");
+    }
+
+    Ok(())
+}
+
+pub fn write_default_config() -> anyhow::Result<()> {
+    let path = config_file_path();
+    if fs::exists(&path)? {
+        bail!("Config file already exists at: {}", &path.display());
+    }
+
+    write_config(&Config::default())?;
+    println!("Written config to {}", path.display());
+    Ok(())
+}
+
+fn write_config(config: &Config) -> Result<()> {
+    let path = config_file_path();
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, toml::to_string_pretty(config)?)?;
+    Ok(())
+}
+
+/// A config passed with `--config` or `RPIO_CONFIG` has to be valid, only the config commands may
+/// start from a file that does not exist yet
+fn load_explicit_config(command: &CommandsCli) -> Result<Config> {
+    let path = config_file_path();
+    if !path.exists() && !matches!(command, CommandsCli::Config { .. }) {
+        bail!(
+            "Config file {} does not exist, create it with `rpio config init`",
+            path.display()
+        );
+    }
+    read_config_file()
+}
+
+/// Unlike `load_config` this fails on an invalid file, so it is never overwritten with defaults
+fn read_config_file() -> Result<Config> {
+    let path = config_file_path();
+    if !path.exists() {
+        return Ok(Config::default());
+    }
+
+    let contents = fs::read_to_string(&path)?;
+    toml::from_str(&contents).map_err(|e| anyhow!("Could not parse {}: {e}", path.display()))
+}
+
+fn set_host_ignored(host: &str, ignored: bool) -> Result<()> {
+    let mut config = read_config_file()?;
+    let is_ignored = config.ignore_hosts.iter().any(|h| h == host);
+
+    match (ignored, is_ignored) {
+        (true, true) => println!("{host} is already in ignore_hosts"),
+        (false, false) => println!("{host} is not in ignore_hosts"),
+        (true, false) => {
+            config.ignore_hosts.push(host.to_owned());
+            write_config(&config)?;
+            println!("✔ Added {host} to ignore_hosts");
+        }
+        (false, true) => {
+            config.ignore_hosts.retain(|h| h != host);
+            write_config(&config)?;
+            println!("✔ Removed {host} from ignore_hosts");
+        }
+    }
+
+    Ok(())
+}
+
+/// `host:app` or `app`, the host can include a port like `--host` does
+fn split_alias_target(target: &str) -> Result<(Option<&str>, &str)> {
+    let (host, app) = match target.rsplit_once(':') {
+        Some((host, app)) => (Some(host), app),
+        None => (None, target),
+    };
+    if app.is_empty() || host.is_some_and(str::is_empty) {
+        bail!("Invalid alias target '{target}': expected 'host:app' or 'app'");
+    }
+    Ok((host, app))
+}
+
+fn add_alias(name: &str, target: &str) -> Result<()> {
+    split_alias_target(target)?;
+    let mut config = read_config_file()?;
+    config.aliases.insert(name.to_owned(), target.to_owned());
+    write_config(&config)?;
+    println!("✔ {name} now expands to {target}");
+
+    Ok(())
+}
+
+fn remove_alias(name: &str) -> Result<()> {
+    let mut config = read_config_file()?;
+    if config.aliases.remove(name).is_none() {
+        println!("{name} is not an alias");
+        return Ok(());
+    }
+    write_config(&config)?;
+    println!("✔ Removed the alias {name}");
+
+    Ok(())
+}
+
+fn set_app_default(
+    host: &str,
+    app_name: &str,
+    container_name: Option<String>,
+    remote_port: Option<u16>,
+) -> Result<()> {
+    let remote_app = RemoteApp::from_host_and_app(host, app_name)?;
+    let key = app_defaults_key(&remote_app);
+    let mut config = read_config_file()?;
+
+    let defaults = config.app_defaults.entry(key.to_owned()).or_default();
+    if container_name.is_some() {
+        defaults.container_name = container_name;
+    }
+    if remote_port.is_some() {
+        defaults.remote_port = remote_port;
+    }
+    write_config(&config)?;
+    println!("✔ Saved the tunnel defaults of {key}");
+
+    Ok(())
+}
+
+fn show_config(config: &Config, output: OutputFormat) -> Result<()> {
+    if output == OutputFormat::Json {
+        println!("{}", serde_json::to_string_pretty(config)?);
+        return Ok(());
+    }
+
+    let path = config_file_path();
+    if path.exists() {
+        println!("# Loaded from {}", path.display());
+        println!("# Fields missing from this file fall back to their default value");
+    } else {
+        println!("# No config file found at {}", path.display());
+        println!("# Using the default configuration");
+    }
+    println!("# Only hosts matching only_hosts are indexed (all hosts when empty),");
+    println!("# hosts matching ignore_hosts are skipped afterwards");
+    print!("{}", toml::to_string_pretty(config)?);
+
+    Ok(())
+}
+
+fn print_paths() {
+    let paths = [
+        ("config file", config_file_path()),
+        ("cache dir", cache_dir()),
+        ("servers cache", servers_cache_path()),
+        ("containers cache", container_cache_path()),
+        ("previews cache", previews_cache_dir()),
+        ("usage history", history_path()),
+        ("background tunnels", tunnels_state_path()),
+    ];
+
+    for (label, path) in paths {
+        let marker = if path.exists() { "✔" } else { "✘" };
+        println!("{marker} {label}: {}", path.display());
+    }
+}
+
+/// Expands `~/` in `identity_file` and fails before ssh does when the key can not be read
+fn check_identity_file(config: &mut Config) -> Result<()> {
+    let Some(path) = &config.identity_file else {
+        return Ok(());
+    };
+    let path = match path.strip_prefix("~") {
+        Ok(rest) => dirs::home_dir()
+            .ok_or_else(|| anyhow!("Could not determine your home directory"))?
+            .join(rest),
+        Err(_) => path.to_owned(),
+    };
+    if !path.is_file() {
+        bail!("Identity file {} does not exist", path.display());
+    }
+    if let Err(e) = fs::File::open(&path) {
+        bail!("Identity file {} can not be read: {e}", path.display());
+    }
+
+    config.identity_file = Some(path);
+    Ok(())
+}
+
+pub fn init_runtime_dirs(cfg: &Config) -> anyhow::Result<()> {
+    fs::create_dir_all(&cfg.cache_dir)?;
+    Ok(())
+}
+
+/// Bumped whenever `ServersCache` changes in a way older versions cannot read
+const SERVERS_CACHE_VERSION: u32 = 2;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServersCache {
+    /// See `read_servers_cache` for how older versions are migrated
+    pub version: u32,
+    pub servers: BTreeMap<String, ServerEntry>,
+}
+
+/// Caches from before the version was tracked
+fn first_cache_version() -> u32 {
+    1
+}
+
+impl ServersCache {
+    fn new(servers: BTreeMap<String, ServerEntry>) -> Self {
+        ServersCache {
+            version: SERVERS_CACHE_VERSION,
+            servers,
+        }
+    }
+
+    fn find_folder(&self, config: &Config, remote_app: &RemoteApp) -> Option<&DataFolder> {
+        let directory = remote_app.remote_directory(config);
+        self.servers
+            .get(&remote_app.host)?
+            .data_folders
+            .iter()
+            .find(|folder| folder.directory() == directory)
+    }
+
+    /// E.g. "Indexed 3 hosts, 12 apps (1 unreachable)"
+    fn summary(&self) -> String {
+        let apps: usize = self.servers.values().map(|e| e.data_folders.len()).sum();
+        let unreachable = self.servers.values().filter(|e| e.error.is_some()).count();
+        format!(
+            "Indexed {} hosts, {apps} apps ({unreachable} unreachable)",
+            self.servers.len()
+        )
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct ServerEntry {
     pub last_updated: i64, // unix timestamp
+    #[serde(default)]
+    pub host_name: Option<String>, // resolved `user@hostname:port` from the ssh config
+    #[serde(default)]
+    pub error: Option<String>, // set when the host could not be indexed
     pub data_folders: Vec<DataFolder>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
-pub struct DataFolder {
-    pub path: String,
-    pub container: Option<String>,
+impl ServerEntry {
+    // A ttl of 0 means the entry is always considered expired
+    fn is_expired(&self, ttl_seconds: u64, now: i64) -> bool {
+        now.saturating_sub(self.last_updated) >= i64::try_from(ttl_seconds).unwrap_or(i64::MAX)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DataFolder {
+    pub path: String,
+    pub root: String, // data root the app was found in
+    pub container: Option<String>,
+}
+
+impl DataFolder {
+    fn directory(&self) -> String {
+        format!("{}/{}", self.root.trim_end_matches('/'), self.path)
+    }
+
+    /// Apps outside of the host's default root are prefixed with their root to keep them apart
+    fn display_name(&self, default_root: &str) -> String {
+        if self.root == default_root {
+            self.path.to_owned()
+        } else {
+            self.directory()
+        }
+    }
+}
+
+/// Runs an indexing script on `host`, retrying with exponential backoff when ssh could not connect.
+/// A script that ran but failed is not retried.
+fn indexing_ssh_output(config: &Config, host: &str, script: &str) -> Result<Output> {
+    let mut attempt = 0;
+    loop {
+        let output = ssh_command(config, host, None)
+            .arg(script)
+            .logged_output()
+            .map_err(tool_error("ssh"))?;
+
+        // ssh exits with 255 when the connection itself failed or timed out
+        if output.status.code() != Some(255) || attempt >= config.ssh_retries {
+            return Ok(output);
+        }
+
+        thread::sleep(Duration::from_secs(1 << attempt.min(6)));
+        attempt += 1;
+    }
+}
+
+/// Fails when the host could not be reached, a host without apps returns an empty list
+fn fetch_data_folders(config: &Config, host: &str) -> Result<Vec<DataFolder>> {
+    let roots: Vec<String> = config
+        .data_roots_for(host)
+        .iter()
+        .map(|root| quote(root))
+        .collect();
+    // Only directories are apps, with `require_compose_file` they also need one of the compose files
+    let mut checks = "[ -d \"$root/$app\" ] || continue;".to_owned();
+    if config.require_compose_file {
+        let tests: Vec<String> = COMPOSE_FILE_NAMES
+            .iter()
+            .map(|name| format!("[ -f \"$root/$app/{name}\" ]"))
+            .collect();
+        checks.push_str(&format!(" {{ {}; }} || continue;", tests.join(" || ")));
+    }
+    let script = format!(
+        "for root in {}; do ls -1 \"$root\" 2>/dev/null | while IFS= read -r app; do {checks} printf '%s\\t%s\\n' \"$root\" \"$app\"; done; done",
+        roots.join(" ")
+    );
+    let ignored_folders = build_glob_set(&config.ignore_folders)?;
+    let output = indexing_ssh_output(config, host, &script)?;
+
+    // Still no connection after all retries
+    if output.status.code() == Some(255) {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} ({})", stderr.trim(), output.status);
+    }
+
+    if !output.status.success() {
+        return Ok(Vec::new()); // same as `|| true`
+    }
+
+    let folders: Vec<DataFolder> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(_, folder)| !folder.trim().is_empty() && !ignored_folders.is_match(folder))
+        .map(|(root, folder)| DataFolder {
+            path: folder.to_string(),
+            root: root.to_string(),
+            container: None,
+        })
+        .collect();
+
+    Ok(sorted_data_folders(folders))
+}
+
+/// Sorted by app, a root that is configured twice lists its apps twice so duplicates are dropped
+fn sorted_data_folders(mut folders: Vec<DataFolder>) -> Vec<DataFolder> {
+    folders.sort_by(|a, b| (&a.path, &a.root).cmp(&(&b.path, &b.root)));
+    folders.dedup_by(|a, b| a.path == b.path && a.root == b.root);
+    folders
+}
+
+/// Fills in `DataFolder.container` with the first container `docker compose ps` reports for each app
+fn fetch_primary_containers(
+    config: &Config,
+    host: &str,
+    mut folders: Vec<DataFolder>,
+) -> Result<Vec<DataFolder>> {
+    if folders.is_empty() {
+        return Ok(folders);
+    }
+
+    let dirs: Vec<String> = folders.iter().map(|f| quote(&f.directory())).collect();
+    let script = format!(
+        "for dir in {}; do printf '%s\\t%s\\n' \"$dir\" \"$(cd \"$dir\" 2>/dev/null && {} ps --format '{{{{.Names}}}}' 2>/dev/null | head -n1)\"; done",
+        dirs.join(" "),
+        docker_compose()
+    );
+    let output = indexing_ssh_output(config, host, &script)?;
+
+    if !output.status.success() {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        bail!("{} ({})", stderr.trim(), output.status);
+    }
+
+    let containers: BTreeMap<String, String> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .filter(|(_, container)| !container.trim().is_empty())
+        .map(|(dir, container)| (dir.to_owned(), container.trim().to_owned()))
+        .collect();
+
+    for folder in &mut folders {
+        folder.container = containers.get(&folder.directory()).cloned();
+    }
+
+    Ok(folders)
+}
+
+/// The servers cache as is, an unreadable cache counts as empty
+pub fn load_servers_cache(config: &Config) -> ServersCache {
+    read_servers_cache(config)
+        .ok()
+        .flatten()
+        .unwrap_or_else(|| ServersCache::new(BTreeMap::new()))
+}
+
+/// `None` when there is no cache yet, fails when the file is corrupt or written by a newer version.
+/// Caches written by older versions are migrated and saved again.
+fn read_servers_cache(config: &Config) -> Result<Option<ServersCache>> {
+    let path = servers_cache_path();
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => bail!("Could not read {}: {e}", path.display()),
+    };
+
+    let (cache, version) = parse_servers_cache(config, &path, &contents)?;
+    if version != SERVERS_CACHE_VERSION {
+        write_servers_cache(&cache)?;
+    }
+
+    Ok(Some(cache))
+}
+
+/// Migrates `contents` to the current version, also returns the version they were written with
+fn parse_servers_cache(
+    config: &Config,
+    path: &Path,
+    contents: &str,
+) -> Result<(ServersCache, u32)> {
+    let parse_error = |e: toml::de::Error| {
+        anyhow!(
+            "Could not parse {}: {}",
+            path.display(),
+            e.message().trim().replace('\n', ", ")
+        )
+    };
+
+    let mut table: toml::Table = toml::from_str(contents).map_err(parse_error)?;
+    let version = match table.get("version") {
+        Some(version) => version
+            .as_integer()
+            .and_then(|v| u32::try_from(v).ok())
+            .ok_or_else(|| anyhow!("{} has an invalid version", path.display()))?,
+        None => first_cache_version(),
+    };
+    if version > SERVERS_CACHE_VERSION {
+        bail!(newer_servers_cache_message(version));
+    }
+
+    for from in version..SERVERS_CACHE_VERSION {
+        if from == 1 {
+            migrate_servers_cache_v1(config, &mut table);
+        }
+    }
+    table.insert("version".to_owned(), SERVERS_CACHE_VERSION.into());
+
+    let cache: ServersCache = table.try_into().map_err(parse_error)?;
+
+    Ok((cache, version))
+}
+
+/// Version 1 left out the root of apps found in the host's default data root
+fn migrate_servers_cache_v1(config: &Config, table: &mut toml::Table) {
+    let Some(servers) = table.get_mut("servers").and_then(|s| s.as_table_mut()) else {
+        return;
+    };
+
+    for (host, entry) in servers.iter_mut() {
+        let folders = entry
+            .get_mut("data_folders")
+            .and_then(|folders| folders.as_array_mut())
+            .into_iter()
+            .flatten()
+            .filter_map(|folder| folder.as_table_mut());
+        for folder in folders {
+            folder
+                .entry("root")
+                .or_insert_with(|| config.default_data_root(host).into());
+        }
+    }
+}
+
+fn clear_servers_cache(yes: bool) -> Result<()> {
+    let path = servers_cache_path();
+    if !path.exists() {
+        eprintln!("No servers cache found at {}", path.display());
+        return Ok(());
+    }
+
+    if !yes && !prompt_confirm(&format!("Delete {}?", path.display()))? {
+        println!("Cancelled");
+        return Ok(());
+    }
+
+    fs::remove_file(&path)?;
+    println!("✔ Removed {}", path.display());
+
+    Ok(())
+}
+
+fn show_cache_info(config: &Config, output: OutputFormat) -> Result<()> {
+    ensure_servers_cache_not_newer()?;
+    let cache = load_servers_cache(config);
+    let now = Utc::now().timestamp();
+
+    if output == OutputFormat::Json {
+        let hosts: Vec<serde_json::Value> = cache
+            .servers
+            .iter()
+            .map(|(host, entry)| {
+                serde_json::json!({
+                    "host": host,
+                    "last_updated": entry.last_updated,
+                    "apps": entry.data_folders.len(),
+                    "stale": entry.is_expired(config.cache_ttl_seconds, now),
+                    "error": entry.error,
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&hosts)?);
+        return Ok(());
+    }
+
+    if cache.servers.is_empty() {
+        eprintln!("The servers cache is empty, run `rpio apps --refresh` to index your hosts");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = cache
+        .servers
+        .iter()
+        .map(|(host, entry)| {
+            let status = if entry.error.is_some() {
+                "unreachable"
+            } else if entry.is_expired(config.cache_ttl_seconds, now) {
+                "stale"
+            } else {
+                "fresh"
+            };
+            vec![
+                host.to_owned(),
+                format_age(now.saturating_sub(entry.last_updated)),
+                entry.data_folders.len().to_string(),
+                status.to_owned(),
+            ]
+        })
+        .collect();
+    print_table(&["HOST", "INDEXED", "APPS", "STATUS"], &rows);
+
+    Ok(())
+}
+
+/// Renders a number of seconds like "3 hours ago"
+fn format_age(seconds: i64) -> String {
+    let (amount, unit) = match seconds {
+        ..60 => return "just now".to_owned(),
+        60..3600 => (seconds / 60, "minute"),
+        3600..86400 => (seconds / 3600, "hour"),
+        _ => (seconds / 86400, "day"),
+    };
+    let plural = if amount == 1 { "" } else { "s" };
+    format!("{amount} {unit}{plural} ago")
+}
+
+fn servers_cache_path() -> PathBuf {
+    let cache_folder = cache_dir();
+    cache_folder.join("servers.toml")
+}
+
+fn previews_cache_dir() -> PathBuf {
+    cache_dir().join("previews")
+}
+
+fn newer_servers_cache_message(version: u32) -> String {
+    format!(
+        "{} was written by a newer rpio (cache version {version}, this one understands up to {SERVERS_CACHE_VERSION}), upgrade rpio or run `rpio cache clear`",
+        servers_cache_path().display()
+    )
+}
+
+/// Fails when the cache on disk was written by a newer rpio, so an older binary never replaces it
+fn ensure_servers_cache_not_newer() -> Result<()> {
+    let version = fs::read_to_string(servers_cache_path())
+        .ok()
+        .and_then(|contents| toml::from_str::<toml::Table>(&contents).ok())
+        .and_then(|table| table.get("version")?.as_integer())
+        .and_then(|version| u32::try_from(version).ok());
+    match version {
+        Some(version) if version > SERVERS_CACHE_VERSION => {
+            bail!(newer_servers_cache_message(version))
+        }
+        _ => Ok(()),
+    }
+}
+
+pub fn write_servers_cache(cache: &ServersCache) -> anyhow::Result<()> {
+    ensure_servers_cache_not_newer()?;
+
+    write_atomically(&servers_cache_path(), &toml::to_string_pretty(cache)?)
+}
+
+fn container_ip_command(config: &Config, remote_app: &RemoteApp, container: &str) -> Command {
+    let mut command = remote_app.ssh_command(config);
+    command.arg(format!(
+        "docker inspect -f '{{{{range $name, $network := .NetworkSettings.Networks}}}}{{{{println $name $network.IPAddress}}}}{{{{end}}}}' {}",
+        quote(container)
+    ));
+    command
+}
+
+/// `address:port` on the remote host where `remote_port` of the container is published
+fn resolve_published_target(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container: &str,
+    remote_port: u16,
+) -> Result<String> {
+    let statuses = remote_app
+        .fetch_container_statuses(config)?
+        .ok_or_else(|| {
+            anyhow!(
+                "docker compose on {} does not support json output",
+                remote_app.host
+            )
+        })?;
+    let status = statuses
+        .iter()
+        .find(|status| status.name == container)
+        .ok_or_else(|| anyhow!("Container {container} is not running"))?;
+    let publisher = status
+        .publishers
+        .iter()
+        .find(|p| p.target_port == remote_port && p.published_port != 0)
+        .ok_or_else(|| anyhow!("Container {container} does not publish port {remote_port}"))?;
+
+    // A port published on all interfaces is reachable through the loopback address
+    let address = match publisher.url.as_str() {
+        "" | "0.0.0.0" => "127.0.0.1".to_owned(),
+        "::" => "[::1]".to_owned(),
+        url if url.contains(':') => format!("[{url}]"),
+        url => url.to_owned(),
+    };
+
+    Ok(format!("{address}:{}", publisher.published_port))
+}
+
+/// IP address of the container, asks which network to use when it is attached to several
+fn resolve_container_ip(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container: &str,
+) -> Result<IpAddr> {
+    let spinner = create_and_start_spinner("Retrieving container IP");
+    let output = container_ip_command(config, remote_app, container)
+        .logged_output()
+        .map_err(tool_error("ssh"))?;
+
+    spinner.finish();
+
+    // One `<network> <ip>` line per network, the ip is empty when the container is not running
+    let networks: Vec<(String, IpAddr)> = String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (network, ip) = line.trim().split_once(' ')?;
+            Some((network.to_owned(), ip.trim().parse().ok()?))
+        })
+        .collect();
+
+    match networks.as_slice() {
+        [] => bail!("Container {container} has no network IP, is it running?"),
+        [(_, ip)] => Ok(*ip),
+        _ => {
+            let lines: Vec<String> = networks
+                .iter()
+                .map(|(network, ip)| format!("{network} {ip}"))
+                .collect();
+            let header = format!("Container {container} is attached to several networks");
+            let selected = run_fzf(&lines, "Choose a network", Some(&header), "", None)?
+                .ok_or_else(|| anyhow!("No network selected"))?;
+            networks
+                .iter()
+                .find(|(network, ip)| format!("{network} {ip}") == selected)
+                .map(|(_, ip)| *ip)
+                .ok_or_else(|| anyhow!("No network selected"))
+        }
+    }
 }
 
-fn fetch_data_folders(host: &str) -> Vec<DataFolder> {
-    let output = Command::new("ssh")
+/// `target` is the `address:port` to forward to, as seen from the remote host
+fn tunnel_command(
+    config: &Config,
+    remote_app: &RemoteApp,
+    host_port: u16,
+    target: &str,
+) -> Command {
+    let mut command = remote_app.ssh_command(config);
+    command
+        .arg("-L")
+        .arg(format!("{host_port}:{target}"))
+        .arg("-N")
+        .arg("-o")
+        .arg("ExitOnForwardFailure=yes")
         .arg("-o")
-        .arg("ConnectTimeout=30")
-        .arg(host)
-        .arg("ls -1 /data 2>/dev/null")
-        .output();
-
-    match output {
-        Ok(out) if out.status.success() => String::from_utf8_lossy(&out.stdout)
-            .lines()
-            .filter(|l| !l.trim().is_empty())
-            .map(|folder| DataFolder {
-                path: format!("{}", folder),
-                container: None,
+        .arg("ServerAliveInterval=60");
+    command
+}
+
+fn find_free_port() -> Result<u16> {
+    // The listener is dropped right away so ssh can bind the port
+    let listener = TcpListener::bind("127.0.0.1:0")?;
+    Ok(listener.local_addr()?.port())
+}
+
+/// With `via_published` the tunnel goes to the port the container publishes on the host
+/// instead of to the container IP, which also works for containers on an isolated network
+fn run_container_tunnel(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container: &str,
+    host_port: u16,
+    remote_port: u16,
+    via_published: bool,
+    background: bool,
+) -> Result<()> {
+    let target = if via_published {
+        resolve_published_target(config, remote_app, container, remote_port)?
+    } else {
+        // ssh expects IPv6 addresses between brackets in a forward
+        match resolve_container_ip(config, remote_app, container)? {
+            IpAddr::V4(ip) => format!("{ip}:{remote_port}"),
+            IpAddr::V6(ip) => format!("[{ip}]:{remote_port}"),
+        }
+    };
+
+    if background {
+        return start_background_tunnel(config, remote_app, host_port, &target);
+    }
+
+    // Ctrl+C only closes the tunnel, so the "next time" hint can still be printed afterwards
+    let interrupted = Arc::new(AtomicBool::new(false));
+    let handler_flag = Arc::clone(&interrupted);
+    ctrlc::set_handler(move || handler_flag.store(true, Ordering::Relaxed))?;
+
+    let mut child = tunnel_command(config, remote_app, host_port, &target)
+        .logged_spawn()
+        .map_err(tool_error("ssh"))?;
+
+    if !quiet() {
+        println!("Opening tunnel on http://localhost:{host_port}");
+        println!("Press Ctrl+C to exit");
+    }
+
+    // ssh usually gets the SIGINT from the terminal as well, but not when only rpio is signalled
+    while child.try_wait()?.is_none() {
+        if interrupted.load(Ordering::Relaxed) {
+            child.kill().ok();
+            child.wait()?;
+            break;
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    if !quiet() {
+        println!();
+    }
+
+    Ok(())
+}
+
+fn start_background_tunnel(
+    config: &Config,
+    remote_app: &RemoteApp,
+    host_port: u16,
+    target: &str,
+) -> Result<()> {
+    let mut command = tunnel_command(config, remote_app, host_port, target);
+    command
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        // A separate process group keeps Ctrl+C in this terminal from closing the tunnel
+        .process_group(0);
+    let mut child = command.logged_spawn().map_err(tool_error("ssh"))?;
+
+    // ExitOnForwardFailure makes ssh exit when the forward can not be set up
+    thread::sleep(Duration::from_secs(1));
+    if let Some(status) = child.try_wait()? {
+        bail!("The tunnel exited right away ({status}), is port {host_port} already in use?");
+    }
+
+    register_tunnel(BackgroundTunnel {
+        pid: child.id(),
+        host: remote_app.host_with_port(),
+        app_name: remote_app.qualified_name(),
+        host_port,
+        target: target.to_owned(),
+        started_at: Utc::now().timestamp(),
+    })?;
+
+    if !quiet() {
+        println!("Tunnel running in the background on http://localhost:{host_port}");
+        println!("Stop it with: rpio tunnels stop {host_port}");
+    }
+
+    Ok(())
+}
+
+fn list_tunnels(output: OutputFormat) -> Result<()> {
+    let tunnels = load_tunnels()?;
+    let now = Utc::now().timestamp();
+
+    if output == OutputFormat::Json {
+        let tunnels: Vec<serde_json::Value> = tunnels
+            .iter()
+            .map(|tunnel| {
+                serde_json::json!({
+                    "pid": tunnel.pid,
+                    "host": tunnel.host,
+                    "app_name": tunnel.app_name,
+                    "host_port": tunnel.host_port,
+                    "target": tunnel.target,
+                    "started_at": tunnel.started_at,
+                })
             })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&tunnels)?);
+        return Ok(());
+    }
+
+    if tunnels.is_empty() {
+        println!("No background tunnels running");
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = tunnels
+        .iter()
+        .map(|tunnel| {
+            vec![
+                format!("localhost:{}", tunnel.host_port),
+                format!("{}:{}", tunnel.app_name, tunnel.host),
+                tunnel.target.to_owned(),
+                tunnel.pid.to_string(),
+                format_age(now - tunnel.started_at),
+            ]
+        })
+        .collect();
+    print_table(&["LOCAL", "APP", "TARGET", "PID", "STARTED"], &rows);
+
+    Ok(())
+}
+
+fn stop_tunnels(port: Option<u16>, all: bool) -> Result<()> {
+    let tunnels = load_tunnels()?;
+    if tunnels.is_empty() {
+        println!("No background tunnels running");
+        return Ok(());
+    }
+
+    let selected: Vec<&BackgroundTunnel> = match port {
+        _ if all => tunnels.iter().collect(),
+        Some(port) => tunnels
+            .iter()
+            .filter(|tunnel| tunnel.host_port == port)
             .collect(),
-        _ => Vec::new(), // same as `|| true`
+        None => {
+            let lines: Vec<String> = tunnels
+                .iter()
+                .map(|tunnel| format!("{} {}:{}", tunnel.host_port, tunnel.app_name, tunnel.host))
+                .collect();
+            let header = "Background tunnels as local port and app:host";
+            let Some(line) = run_fzf(&lines, "Choose a tunnel to stop", Some(header), "", None)?
+            else {
+                return Ok(());
+            };
+            tunnels
+                .iter()
+                .zip(&lines)
+                .filter(|(_, l)| **l == line)
+                .map(|(tunnel, _)| tunnel)
+                .collect()
+        }
+    };
+    if selected.is_empty() {
+        bail!("No background tunnel on port {}", port.unwrap_or_default());
     }
+
+    for tunnel in selected {
+        stop_tunnel(tunnel)?;
+        println!(
+            "✔ Stopped the tunnel on localhost:{} to {}:{}",
+            tunnel.host_port, tunnel.app_name, tunnel.host
+        );
+    }
+
+    Ok(())
 }
 
-pub fn load_servers_cache() -> ServersCache {
-    let path = servers_cache_path();
+fn retrieve_local_path(sw_root_folder: &Path, is_backup: bool) -> PathBuf {
+    sw_root_folder.join(if is_backup { "data/db" } else { "data/files" })
+}
+
+/// `backup` only copies that file from the backups folder instead of the whole folder,
+/// `bwlimit` falls back to `rsync_bwlimit` from the config and `preview` only itemizes the changes
+fn rsync_command(
+    config: &Config,
+    remote_app: &RemoteApp,
+    sw_root_folder: &Path,
+    is_backup: bool,
+    backup: Option<&str>,
+    bwlimit: Option<u32>,
+    preview: bool,
+) -> Command {
+    let host = &remote_app.host;
+    let app_dir = remote_app.remote_directory(config);
+    let hostpath = match (is_backup, backup) {
+        (true, Some(backup)) => format!("{app_dir}/data/db/backups/{backup}"),
+        (true, None) => format!("{app_dir}/data/db/backups"),
+        (false, _) => format!("{app_dir}/data/files/"),
+    };
+
+    let mut command = Command::new("rsync");
+    command.arg("-azv").arg("--partial");
+    if preview {
+        command.arg("--dry-run").arg("--itemize-changes");
+    } else {
+        command.arg("--info=progress2");
+    }
+    if let Some(bwlimit) = bwlimit.or(config.rsync_bwlimit) {
+        command.arg(format!("--bwlimit={bwlimit}"));
+    }
+    command
+        .arg("-e")
+        .arg(remote_app.rsync_remote_shell(config))
+        .arg(format!("{host}:{hostpath}"))
+        .arg(retrieve_local_path(sw_root_folder, is_backup));
+    command
+}
+
+fn restore_backup_or_files(
+    config: &Config,
+    remote_app: &RemoteApp,
+    sw_root_folder: &Path,
+    is_backup: bool,
+    backup: Option<&str>,
+    bwlimit: Option<u32>,
+    preview: bool,
+) -> Result<()> {
+    let mut command = rsync_command(
+        config,
+        remote_app,
+        sw_root_folder,
+        is_backup,
+        backup,
+        bwlimit,
+        preview,
+    );
+    if preview {
+        return preview_transfer(command);
+    }
+
+    let loading_message = if is_backup {
+        "Retrieving backup files"
+    } else {
+        "Retrieving files"
+    };
+    let spinner = create_and_start_timed_spinner(loading_message);
+    std::fs::create_dir_all(retrieve_local_path(sw_root_folder, is_backup))?;
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .logged_spawn()
+        .map_err(tool_error("rsync"))?;
+
+    let mut stderr = child.stderr.take().expect("piped stderr");
+    let stderr_reader = thread::spawn(move || {
+        let mut error_message = String::new();
+        stderr.read_to_string(&mut error_message).ok();
+        error_message
+    });
+
+    // rsync rewrites the progress line with `\r`, the spinner keeps spinning until one parses
+    let stdout = BufReader::new(child.stdout.take().expect("piped stdout"));
+    for chunk in stdout.split(b'\r') {
+        for line in chunk?.split(|b| *b == b'\n') {
+            if let Some((percent, transferred, rate)) =
+                parse_rsync_progress(&String::from_utf8_lossy(line))
+            {
+                set_progress(
+                    &spinner,
+                    percent,
+                    &format!("{loading_message} ({transferred} bytes, {rate})"),
+                );
+            }
+        }
+    }
+
+    let status = child.wait()?;
+    let error_message = stderr_reader.join().unwrap_or_default();
+    spinner.finish();
+    if !status.success() {
+        return Err(anyhow::anyhow!(
+            "rsync failed with status {}: {}",
+            status,
+            error_message
+        ));
+    }
 
-    match fs::read_to_string(&path) {
-        Ok(contents) => toml::from_str(&contents).unwrap_or_else(|_| empty_cache()),
-        Err(_) => empty_cache(),
+    Ok(())
+}
+
+/// Prints the itemized changes of an rsync `--dry-run` without copying anything
+fn preview_transfer(mut command: Command) -> Result<()> {
+    let spinner = create_and_start_spinner("Comparing remote and local files");
+    let output = command.logged_output().map_err(tool_error("rsync"))?;
+    spinner.finish_and_clear();
+    if !output.status.success() {
+        bail!(
+            "rsync failed with status {}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+    print!("{}", String::from_utf8_lossy(&output.stdout));
+
+    Ok(())
+}
+
+/// Parses a `--info=progress2` line like `  1,234,567  45%  1.23MB/s    0:00:12`
+fn parse_rsync_progress(line: &str) -> Option<(u64, String, String)> {
+    let mut fields = line.split_whitespace();
+    let transferred = fields.next()?;
+    let percent = fields.next()?.strip_suffix('%')?.parse().ok()?;
+    let rate = fields.next()?;
+    if !transferred.chars().all(|c| c.is_ascii_digit() || c == ',') {
+        return None;
     }
+
+    Some((percent, transferred.to_owned(), rate.to_owned()))
+}
+
+fn ssh_session_command(config: &Config, remote_app: &RemoteApp) -> Command {
+    let app_dir = quote(&remote_app.remote_directory(config));
+    let mut command = remote_app.ssh_command(config);
+    command
+        .arg("-t")
+        .arg(format!("cd {app_dir} ; bash --login"));
+    command
 }
 
-fn empty_cache() -> ServersCache {
-    ServersCache {
-        servers: BTreeMap::new(),
+fn logs_command(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container_name: Option<&str>,
+    follow: bool,
+    tail: Option<u32>,
+) -> Command {
+    let app_dir = quote(&remote_app.remote_directory(config));
+    // `docker compose logs` expects service names, single containers go through `docker logs`
+    let mut logs = match container_name {
+        Some(_) => "docker logs".to_owned(),
+        None => format!("{} logs", docker_compose()),
+    };
+    if follow {
+        logs.push_str(" -f");
+    }
+    if let Some(tail) = tail {
+        logs.push_str(&format!(" --tail {tail}"));
     }
+    if let Some(container_name) = container_name {
+        logs.push_str(&format!(" {}", quote(container_name)));
+    }
+
+    let mut command = remote_app.ssh_command(config);
+    command.arg("-t").arg(format!("cd {app_dir} && {logs}"));
+    command
+}
+
+fn restart_command(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container_name: Option<&str>,
+) -> Command {
+    let app_dir = quote(&remote_app.remote_directory(config));
+    let restart = match container_name {
+        Some(container_name) => format!("docker restart {}", quote(container_name)),
+        None => format!("{} restart", docker_compose()),
+    };
+
+    let mut command = remote_app.ssh_command(config);
+    command.arg(format!("cd {app_dir} && {restart}"));
+    command
 }
 
-fn ensure_cache_folder() -> Result<()> {
-    std::fs::create_dir_all(project_dirs().cache_dir())?;
+fn restart_containers(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container_name: Option<&str>,
+    yes: bool,
+) -> Result<()> {
+    let target = container_name.unwrap_or(&remote_app.app_name);
+    if !yes && !prompt_confirm(&format!("Restart {target} on {}?", remote_app.host))? {
+        bail!("Restart of {target} cancelled");
+    }
+    let status = restart_command(config, remote_app, container_name)
+        .logged_status()
+        .map_err(tool_error("ssh"))?;
+    if !status.success() {
+        bail!("Restarting {target} failed with {status}");
+    }
+    if !quiet() {
+        println!("✔ Restarted {target}");
+    }
+
     Ok(())
 }
 
-fn servers_cache_path() -> PathBuf {
-    let cache_folder = project_dirs().cache_dir().to_path_buf();
-    cache_folder.join("servers.toml")
+fn exec_command(
+    config: &Config,
+    remote_app: &RemoteApp,
+    container_name: &str,
+    container_command: &[String],
+) -> Command {
+    let app_dir = quote(&remote_app.remote_directory(config));
+    let container_command = if container_command.is_empty() {
+        "/bin/sh -c 'if [ -x /bin/bash ]; then exec /bin/bash; else exec /bin/sh; fi'".to_owned()
+    } else {
+        container_command
+            .iter()
+            .map(|arg| quote(arg))
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+
+    let mut command = remote_app.ssh_command(config);
+    command.arg("-t").arg(format!(
+        "cd {app_dir} && docker exec -it {} {container_command}",
+        quote(container_name)
+    ));
+    command
 }
 
-pub fn write_servers_cache(cache: &ServersCache) -> anyhow::Result<()> {
-    let cache_folder = project_dirs().cache_dir().to_path_buf();
-    let cache_file = cache_folder.join("servers.toml");
+/// Runs on the host itself, the arguments are joined with spaces for the remote shell like ssh does
+fn run_command(config: &Config, remote_app: &RemoteApp, remote_command: &[String]) -> Command {
+    let app_dir = quote(&remote_app.remote_directory(config));
+    let mut command = remote_app.ssh_command(config);
+    command.arg(format!("cd {app_dir} && {}", remote_command.join(" ")));
+    command
+}
+
+fn show_container_statuses(
+    config: &Config,
+    remote_app: &RemoteApp,
+    output: OutputFormat,
+) -> Result<()> {
+    let Some(statuses) = remote_app.fetch_container_statuses(config)? else {
+        if output == OutputFormat::Json {
+            bail!(
+                "docker compose on {} does not support json output",
+                remote_app.host
+            );
+        }
+        // Fall back to docker's own table when it can not output json
+        remote_app
+            .ps_command(config, false)
+            .logged_status()
+            .map_err(tool_error("ssh"))?;
+        return Ok(());
+    };
+
+    if output == OutputFormat::Json {
+        let statuses: Vec<serde_json::Value> = statuses
+            .iter()
+            .map(|status| {
+                serde_json::json!({
+                    "name": status.name,
+                    "state": status.state,
+                    "ports": status.ports(),
+                })
+            })
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&statuses)?);
+        return Ok(());
+    }
 
-    ensure_cache_folder()?;
+    let rows: Vec<Vec<String>> = statuses
+        .iter()
+        .map(|status| {
+            vec![
+                status.name.to_owned(),
+                status.state.to_owned(),
+                status.ports(),
+            ]
+        })
+        .collect();
+    print_table(&["NAME", "STATE", "PORTS"], &rows);
 
-    let mut tmp = NamedTempFile::new_in(&cache_folder)?;
-    let contents = toml::to_string_pretty(cache)?;
+    Ok(())
+}
 
-    tmp.write_all(contents.as_bytes())?;
-    tmp.flush()?;
-    tmp.persist(&cache_file)?;
+/// Lists the environment of `service`, which is picked from the services that have one when not given
+fn show_app_env(
+    config: &Config,
+    remote_app: &RemoteApp,
+    service: Option<&str>,
+    output: OutputFormat,
+) -> Result<()> {
+    let yaml = remote_app.retrieve_app_docker_config(config)?;
+    let doc: Value = serde_yaml::from_str(&yaml)?;
+
+    let service = match service {
+        Some(service) => {
+            if doc.get("services").and_then(|s| s.get(service)).is_none() {
+                bail!("{} has no service named {service}", remote_app.app_name);
+            }
+            service.to_owned()
+        }
+        None => {
+            let services: Vec<String> = doc
+                .get("services")
+                .and_then(|s| s.as_mapping())
+                .into_iter()
+                .flat_map(|services| services.keys())
+                .filter_map(|k| k.as_str())
+                .filter(|service| !service_env(&doc, service).is_empty())
+                .map(str::to_owned)
+                .collect();
+            match services.as_slice() {
+                [] => bail!(
+                    "None of the services of {} set environment variables",
+                    remote_app.app_name
+                ),
+                [service] => service.to_owned(),
+                _ => {
+                    let header = format!(
+                        "Services of {} that set environment variables",
+                        remote_app.app_name
+                    );
+                    run_fzf(&services, "Choose a service", Some(&header), "", None)?
+                        .ok_or_else(|| anyhow!("No service selected"))?
+                }
+            }
+        }
+    };
+
+    let env = service_env(&doc, &service);
+    if output == OutputFormat::Json {
+        let env: serde_json::Map<String, serde_json::Value> = env
+            .into_iter()
+            .map(|(key, value)| (key, value.into()))
+            .collect();
+        println!("{}", serde_json::to_string_pretty(&env)?);
+        return Ok(());
+    }
+
+    let rows: Vec<Vec<String>> = env
+        .into_iter()
+        .map(|(key, value)| vec![key, value])
+        .collect();
+    print_table(&["KEY", "VALUE"], &rows);
 
     Ok(())
 }
 
-fn read_ssh_hosts() -> anyhow::Result<Vec<String>> {
-    let path = dirs::home_dir().expect("home dir").join(".ssh/config");
+fn read_env_file_command(config: &Config, remote_app: &RemoteApp) -> Command {
+    let mut command = remote_app.ssh_command(config);
+    command.arg(format!(
+        "cat {} 2>/dev/null || true",
+        quote(&format!("{}/.env", remote_app.remote_directory(config)))
+    ));
+    command
+}
+
+/// Replaces `.env` with what is passed on stdin
+fn write_env_file_command(config: &Config, remote_app: &RemoteApp) -> Command {
+    let mut command = remote_app.ssh_command(config);
+    command.arg(format!(
+        "cat > {}",
+        quote(&format!("{}/.env", remote_app.remote_directory(config)))
+    ));
+    command
+}
 
-    let contents: String = fs::read_to_string(path)
-        .map_err(|_| anyhow!("Could not open .ssh/config file to read hosts"))?;
+/// Sets the variables in the app's `.env` on the host, existing lines are updated in place.
+/// The containers only pick the new values up once they are recreated.
+fn write_app_env(
+    config: &Config,
+    remote_app: &RemoteApp,
+    vars: &[(String, String)],
+    yes: bool,
+) -> Result<()> {
+    let output = read_env_file_command(config, remote_app)
+        .logged_output()
+        .map_err(tool_error("ssh"))?;
+    if !output.status.success() {
+        bail!(
+            "Could not read the .env of {}: {} ({})",
+            remote_app.app_name,
+            String::from_utf8_lossy(&output.stderr).trim(),
+            output.status
+        );
+    }
 
-    let hosts = contents
+    let mut lines: Vec<String> = String::from_utf8_lossy(&output.stdout)
         .lines()
-        .filter_map(|line| {
-            let line = line.trim();
-            if line.starts_with("Host ") {
-                line.split_whitespace().nth(1).map(String::from)
-            } else {
-                None
-            }
-        })
+        .map(str::to_owned)
         .collect();
+    let mut changes = Vec::new();
+    for (key, value) in vars {
+        let existing = lines.iter().position(|line| {
+            let line = line.trim_start();
+            let line = line.strip_prefix("export ").unwrap_or(line);
+            line.split_once('=').is_some_and(|(k, _)| k.trim() == key)
+        });
+        let line = format!("{key}={value}");
+        match existing {
+            Some(i) => {
+                // Keeps an `export ` prefix, which compose ignores but shell scripts sourcing .env need
+                let indent = lines[i].len() - lines[i].trim_start().len();
+                let export = if lines[i].trim_start().starts_with("export ") {
+                    "export "
+                } else {
+                    ""
+                };
+                let line = format!("{}{export}{line}", &lines[i][..indent]);
+                if lines[i] != line {
+                    changes.push(format!("  {} -> {line}", lines[i]));
+                    lines[i] = line;
+                }
+            }
+            None => {
+                changes.push(format!("  + {line}"));
+                lines.push(line);
+            }
+        }
+    }
 
-    Ok(hosts)
+    if changes.is_empty() {
+        if !quiet() {
+            println!("✔ .env of {} is already up to date", remote_app.app_name);
+        }
+        return Ok(());
+    }
+
+    println!(
+        "Changes to the .env of {} on {}:",
+        remote_app.app_name, remote_app.host
+    );
+    for change in &changes {
+        println!("{change}");
+    }
+    if !yes && !prompt_confirm("Write these changes?")? {
+        bail!("Writing .env cancelled");
+    }
+
+    let mut child = write_env_file_command(config, remote_app)
+        .stdin(Stdio::piped())
+        .logged_spawn()
+        .map_err(tool_error("ssh"))?;
+    let mut contents = lines.join("\n");
+    contents.push('\n');
+    child
+        .stdin
+        .take()
+        .expect("piped stdin")
+        .write_all(contents.as_bytes())?;
+    let status = child.wait()?;
+    if !status.success() {
+        bail!(
+            "Writing the .env of {} failed with {status}",
+            remote_app.app_name
+        );
+    }
+    if !quiet() {
+        println!("✔ Updated .env, restart the app to apply the changes");
+    }
+
+    Ok(())
 }
 
-fn run_container_tunnel(
-    host: &str,
-    container: &str,
-    host_port: u32,
-    remote_port: u32,
+/// Runs the command on every app, one failing app does not stop the others
+fn run_on_apps(
+    config: &Config,
+    remote_apps: &[RemoteApp],
+    application_command: &ApplicationCommand,
+    output: OutputFormat,
 ) -> Result<()> {
-    let spinner = create_and_start_spinner("Retrieving container IP");
-    let output = Command::new("ssh")
-        .arg(&host)
-        .arg(format!("docker inspect -f '{{{{range .NetworkSettings.Networks}}}}{{{{println .IPAddress}}}}{{{{end}}}}' {container} | head -n1"))
-        .output()?;
+    if output == OutputFormat::Json {
+        bail!("--output json can not be combined with --multi");
+    }
+    let targets: Vec<String> = remote_apps
+        .iter()
+        .map(|remote_app| {
+            format!(
+                "{}:{}",
+                remote_app.qualified_name(),
+                remote_app.host_with_port()
+            )
+        })
+        .collect();
+
+    if let ApplicationCommand::Restart { yes: false, .. } = application_command
+        && !prompt_confirm(&format!("Restart {}?", targets.join(", ")))?
+    {
+        bail!("Restart cancelled");
+    }
+
+    let mut rows = Vec::new();
+    let mut failed = 0;
+    for (remote_app, target) in remote_apps.iter().zip(&targets) {
+        println!("==> {target}");
+        let result = match application_command {
+            ApplicationCommand::Restart { container_name, .. } => {
+                restart_containers(config, remote_app, container_name.as_deref(), true)
+            }
+            ApplicationCommand::Ps => show_container_statuses(config, remote_app, output),
+            _ => Err(anyhow!("{application_command} can not run on several apps")),
+        };
+        let status = match result {
+            Ok(()) => "✔ ok".to_owned(),
+            Err(error) => {
+                failed += 1;
+                format!("✘ {error:#}")
+            }
+        };
+        rows.push(vec![target.to_owned(), status]);
+        println!();
+    }
 
-    spinner.finish();
+    print_table(&["APP", "RESULT"], &rows);
 
-    let output_chars = String::from_utf8_lossy(&output.stdout);
+    if failed > 0 {
+        bail!("{failed} of {} apps failed", remote_apps.len());
+    }
 
-    let container_ip = output_chars.trim();
+    Ok(())
+}
 
-    let status = Command::new("ssh")
-        .arg(host)
-        .arg("-L")
-        .arg(format!("{host_port}:{container_ip}:{remote_port}"))
-        .arg("-N")
-        .arg("-o")
-        .arg("ExitOnForwardFailure=yes")
-        .arg("-o")
-        .arg("ServerAliveInterval=60")
-        .spawn()?;
+fn open_in_browser(url: &str) -> Result<()> {
+    let program = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    let status = Command::new(program)
+        .arg(url)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .logged_status()
+        .map_err(tool_error(program))?;
+    if !status.success() {
+        bail!("Could not open {url}, {program} failed with {status}");
+    }
 
-    println!("Opening tunnel on http://localhost:{host_port}");
-    println!("Press Ctrl+C to exit");
+    Ok(())
+}
 
-    status.wait_with_output()?;
+fn attach_ssh_session(config: &Config, remote_app: &RemoteApp) -> Result<()> {
+    ssh_session_command(config, remote_app)
+        .logged_status()
+        .map_err(tool_error("ssh"))?;
 
     Ok(())
 }
 
-fn restore_backup_or_files(
-    host: &str,
-    app: &str,
-    sw_root_folder: &PathBuf,
-    is_backup: bool,
+fn print_dry_run(
+    config: &Config,
+    remote_app: &RemoteApp,
+    application_command: &ApplicationCommand,
+    output: OutputFormat,
 ) -> Result<()> {
-    let hostpath = if is_backup {
-        format!("/data/{app}/data/db/backups")
-    } else {
-        format!("/data/{app}/data/files/")
-    };
-    let mut localpath = sw_root_folder.to_owned();
-    localpath.push(if is_backup { "data/db" } else { "data/files" });
+    let commands = dry_run_commands(config, remote_app, application_command)?;
 
-    let loading_message = if is_backup {
-        "Retrieving backup files"
-    } else {
-        "Retrieving files"
-    };
-    let spinner = create_and_start_spinner(&loading_message);
-    std::fs::create_dir_all(&localpath)?;
-    let mut command = Command::new("rsync");
-    command
-        .arg("-azv")
-        .arg("--partial")
-        .arg("-e")
-        .arg("ssh")
-        .arg(format!("{host}:{hostpath}"))
-        .arg(localpath);
-    let output = command.output()?;
-    spinner.finish();
-    if !output.status.success() {
-        let error_message = String::from_utf8_lossy(&output.stderr);
-        return Err(anyhow::anyhow!(
-            "rsync failed with status {}: {}",
-            output.status,
-            error_message
-        ));
+    if output == OutputFormat::Json {
+        let plan = dry_run_plan(remote_app, application_command, &commands);
+        println!("{}", serde_json::to_string_pretty(&plan)?);
+        return Ok(());
+    }
+
+    for command in &commands {
+        println!("{}", format_command(command));
     }
 
     Ok(())
 }
 
-fn attach_ssh_session(remote_app: &RemoteApp) -> Result<()> {
-    let app_dir = directory_for_app(&remote_app.app_name);
-    let mut command = Command::new("ssh");
-    command
-        .arg("-t")
-        .arg(&remote_app.host)
-        .arg(format!("cd {app_dir} ; bash --login"));
-    command.status()?;
+/// Like `print_dry_run`, the JSON plans of all apps are printed as one array
+fn print_multi_dry_run(
+    config: &Config,
+    remote_apps: &[RemoteApp],
+    application_command: &ApplicationCommand,
+    output: OutputFormat,
+) -> Result<()> {
+    if output != OutputFormat::Json {
+        for remote_app in remote_apps {
+            print_dry_run(config, remote_app, application_command, output)?;
+        }
+        return Ok(());
+    }
+
+    let plans = remote_apps
+        .iter()
+        .map(|remote_app| {
+            let commands = dry_run_commands(config, remote_app, application_command)?;
+            Ok(dry_run_plan(remote_app, application_command, &commands))
+        })
+        .collect::<Result<Vec<serde_json::Value>>>()?;
+    println!("{}", serde_json::to_string_pretty(&plans)?);
 
     Ok(())
 }
 
-fn directory_for_app(app: &str) -> String {
-    format!("/data/{app}")
+fn dry_run_plan(
+    remote_app: &RemoteApp,
+    application_command: &ApplicationCommand,
+    commands: &[Command],
+) -> serde_json::Value {
+    serde_json::json!({
+        "host": remote_app.host,
+        "app_name": remote_app.qualified_name(),
+        "command": application_command.to_string(),
+        "argv": commands.iter().map(command_argv).collect::<Vec<_>>(),
+    })
+}
+
+/// Commands that would run for `application_command`, values only known at runtime are placeholders
+fn dry_run_commands(
+    config: &Config,
+    remote_app: &RemoteApp,
+    application_command: &ApplicationCommand,
+) -> Result<Vec<Command>> {
+    let commands = match application_command {
+        ApplicationCommand::Tunnel {
+            host_port,
+            via_published: true,
+            ..
+        } => vec![
+            remote_app.ps_command(config, true),
+            tunnel_command(config, remote_app, *host_port, "127.0.0.1:<published_port>"),
+        ],
+        ApplicationCommand::Tunnel {
+            container_name,
+            host_port,
+            remote_port,
+            via_published: false,
+            ..
+        } => vec![
+            container_ip_command(config, remote_app, container_name),
+            tunnel_command(
+                config,
+                remote_app,
+                *host_port,
+                &format!("<container_ip>:{remote_port}"),
+            ),
+        ],
+        ApplicationCommand::SshSession => vec![ssh_session_command(config, remote_app)],
+        ApplicationCommand::RetrieveBackup {
+            backup,
+            bwlimit,
+            preview,
+            dest,
+        } => {
+            let root_folder = retrieve_root_folder(config, dest.as_deref())?;
+            vec![rsync_command(
+                config,
+                remote_app,
+                &root_folder,
+                true,
+                backup.as_deref(),
+                *bwlimit,
+                *preview,
+            )]
+        }
+        ApplicationCommand::RetrieveFiles {
+            bwlimit,
+            preview,
+            dest,
+        } => {
+            let root_folder = retrieve_root_folder(config, dest.as_deref())?;
+            vec![rsync_command(
+                config,
+                remote_app,
+                &root_folder,
+                false,
+                None,
+                *bwlimit,
+                *preview,
+            )]
+        }
+        ApplicationCommand::HostedUrl { .. } => vec![remote_app.docker_config_command(config)],
+        ApplicationCommand::Logs {
+            container_name,
+            follow,
+            tail,
+        } => vec![logs_command(
+            config,
+            remote_app,
+            container_name.as_deref(),
+            *follow,
+            *tail,
+        )],
+        ApplicationCommand::Restart { container_name, .. } => vec![restart_command(
+            config,
+            remote_app,
+            container_name.as_deref(),
+        )],
+        ApplicationCommand::Ps => vec![remote_app.ps_command(config, true)],
+        ApplicationCommand::Exec {
+            container_name,
+            command,
+        } => vec![exec_command(config, remote_app, container_name, command)],
+        ApplicationCommand::Run { command } => vec![run_command(config, remote_app, command)],
+        ApplicationCommand::Env { set, .. } if set.is_empty() => {
+            vec![remote_app.docker_config_command(config)]
+        }
+        ApplicationCommand::Env { .. } => vec![
+            read_env_file_command(config, remote_app),
+            write_env_file_command(config, remote_app),
+        ],
+    };
+
+    Ok(commands)
+}
+
+/// Folder the retrieve commands copy into, `dest` skips looking for the local app
+fn retrieve_root_folder(config: &Config, dest: Option<&Path>) -> Result<PathBuf> {
+    match dest {
+        Some(dest) => Ok(dest.to_path_buf()),
+        None => find_semantic_works_root_folder(config),
+    }
 }
 
-fn find_semantic_works_root_folder() -> Result<PathBuf> {
-    let mut current_dir = std::env::current_dir()?;
+/// Files that mark a folder as a semantic.works app regardless of its compose file
+const APP_MARKER_FILES: [&str; 2] = [".mu", "mu.json"];
+
+/// Closest folder upwards from the current directory that has a marker file,
+/// or a `docker-compose.yml` with an image starting with one of `app_images`
+fn find_semantic_works_root_folder(config: &Config) -> Result<PathBuf> {
+    let start_dir = std::env::current_dir()?;
+    let mut current_dir = start_dir.to_owned();
 
     loop {
+        if APP_MARKER_FILES
+            .iter()
+            .any(|marker| current_dir.join(marker).exists())
+        {
+            return Ok(current_dir);
+        }
+
         let compose_file = current_dir.join("docker-compose.yml");
 
         if compose_file.exists() {
             let contents = fs::read_to_string(&compose_file)?;
+            let contents = interpolate(&contents, &read_env_file(&current_dir.join(".env")));
             let doc: Value = serde_yaml::from_str(&contents)?;
 
             if let Some(services) = doc.get("services").and_then(|s| s.as_mapping()) {
                 for (_name, service) in services {
-                    if let Some(image) = service.get("image").and_then(|v| v.as_str()) {
-                        if image.starts_with("semtech/mu-identifier") {
-                            return Ok(current_dir);
-                        }
+                    if let Some(image) = service.get("image").and_then(|v| v.as_str())
+                        && config
+                            .app_images
+                            .iter()
+                            .any(|prefix| image.starts_with(prefix.as_str()))
+                    {
+                        return Ok(current_dir);
                     }
                 }
             }
@@ -557,34 +3576,279 @@ fn find_semantic_works_root_folder() -> Result<PathBuf> {
         }
     }
 
-    bail!("Could not find a semantic.works app in this or any parent directory");
+    bail!(
+        "Could not find a semantic.works app in {} or any parent directory, looked for a {} file or a docker-compose.yml using an image starting with {}",
+        start_dir.display(),
+        APP_MARKER_FILES.join(" or "),
+        config.app_images.join(", ")
+    );
+}
+
+/// Whether any part of the command was picked interactively or automatically,
+/// only then is it useful to print the full command as a hint
+fn was_prompted(commands_cli: &CommandsCli) -> bool {
+    let CommandsCli::Apps {
+        host,
+        app_name,
+        app_command,
+        ..
+    } = commands_cli
+    else {
+        return false;
+    };
+    if host.is_none() || app_name.is_none() {
+        return true;
+    }
+
+    match app_command {
+        None => true,
+        Some(ApplicationCommandCli::Tunnel {
+            container_name,
+            host_port,
+            remote_port,
+            ..
+        }) => container_name.is_none() || host_port.is_none() || remote_port.is_none(),
+        Some(
+            ApplicationCommandCli::Logs { container_name, .. }
+            | ApplicationCommandCli::Restart { container_name, .. }
+            | ApplicationCommandCli::Exec { container_name, .. },
+        ) => container_name.is_none(),
+        Some(ApplicationCommandCli::Run { command }) => command.is_empty(),
+        Some(_) => false,
+    }
+}
+
+fn push_retrieve_flags(
+    command: &mut String,
+    bwlimit: Option<u32>,
+    preview: bool,
+    dest: Option<&Path>,
+) {
+    if let Some(bwlimit) = bwlimit {
+        command.push_str(&format!(" --bwlimit {bwlimit}"));
+    }
+    if preview {
+        command.push_str(" --preview");
+    }
+    if let Some(dest) = dest {
+        command.push_str(&format!(" --dest {}", quote(&dest.to_string_lossy())));
+    }
 }
 
 fn print_application_command(remote_app: &RemoteApp, application_command: &ApplicationCommand) {
     println!("💡 Next time you can run the following command directly:");
-    if let ApplicationCommand::Tunnel {
-        container_name,
-        host_port,
-        remote_port,
-    } = application_command
+    let command = match application_command {
+        ApplicationCommand::Tunnel {
+            container_name,
+            host_port,
+            remote_port,
+            via_published,
+            background,
+        } => {
+            let mut command = format!(
+                "tunnel --container-name {container_name} --host-port {host_port} --remote-port {remote_port}"
+            );
+            if *via_published {
+                command.push_str(" --via-published");
+            }
+            if *background {
+                command.push_str(" --background");
+            }
+            command
+        }
+        ApplicationCommand::Logs {
+            container_name,
+            follow,
+            tail,
+        } => {
+            let mut command = "logs".to_owned();
+            if let Some(container_name) = container_name {
+                command.push_str(&format!(" --container-name {container_name}"));
+            }
+            if *follow {
+                command.push_str(" --follow");
+            }
+            if let Some(tail) = tail {
+                command.push_str(&format!(" --tail {tail}"));
+            }
+            command
+        }
+        ApplicationCommand::Restart {
+            container_name: Some(container_name),
+            ..
+        } => format!("restart --container-name {container_name}"),
+        ApplicationCommand::RetrieveBackup {
+            backup,
+            bwlimit,
+            preview,
+            dest,
+        } => {
+            let mut command = "retrieve-backup".to_owned();
+            if backup.is_none() {
+                command.push_str(" --all");
+            }
+            push_retrieve_flags(&mut command, *bwlimit, *preview, dest.as_deref());
+            command
+        }
+        ApplicationCommand::RetrieveFiles {
+            bwlimit,
+            preview,
+            dest,
+        } => {
+            let mut command = "retrieve-files".to_owned();
+            push_retrieve_flags(&mut command, *bwlimit, *preview, dest.as_deref());
+            command
+        }
+        ApplicationCommand::Exec {
+            container_name,
+            command,
+        } => {
+            let mut hint = format!("exec --container-name {container_name}");
+            if !command.is_empty() {
+                let args: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+                hint.push_str(&format!(" -- {}", args.join(" ")));
+            }
+            hint
+        }
+        ApplicationCommand::Run { command } => {
+            let args: Vec<String> = command.iter().map(|arg| quote(arg)).collect();
+            format!("run -- {}", args.join(" "))
+        }
+        ApplicationCommand::HostedUrl { open: true } => "hosted-url --open".to_owned(),
+        ApplicationCommand::Env { service, set, yes } => {
+            let mut command = "env".to_owned();
+            if let Some(service) = service {
+                command.push_str(&format!(" --service {service}"));
+            }
+            for (key, value) in set {
+                command.push_str(&format!(" --set {}", quote(&format!("{key}={value}"))));
+            }
+            if *yes {
+                command.push_str(" --yes");
+            }
+            command
+        }
+        _ => application_command.to_string(),
+    };
+    println!(
+        "rpio apps {} {} {}",
+        quote(&remote_app.host_with_port()),
+        quote(&remote_app.qualified_name()),
+        command
+    );
+}
+
+/// `RUST_LOG` is used as is, unless `-v` raises the level of rpio's own logs
+fn init_logging(verbose: u8) {
+    let mut builder =
+        env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn"));
+    match verbose {
+        0 => {}
+        1 => {
+            builder.filter_module("rpio", LevelFilter::Debug);
+        }
+        _ => {
+            builder.filter_module("rpio", LevelFilter::Trace);
+        }
+    }
+    builder.format_timestamp(None).init();
+}
+
+/// `rpio apps <HOST> <APP>` is short for `rpio apps --host <HOST> --app-name <APP>`
+fn merge_positional_app(command: &mut CommandsCli) {
+    if let CommandsCli::Apps {
+        host,
+        app_name,
+        host_arg,
+        app_arg,
+        ..
+    } = command
     {
-        println!(
-            "rpio apps --host {} --app-name {} tunnel --container-name {} --host-port {} --remote-port {}",
-            remote_app.host, remote_app.app_name, container_name, host_port, remote_port
-        );
-    } else {
-        println!(
-            "rpio apps --host {} --app-name {} {}",
-            remote_app.host, remote_app.app_name, application_command
-        );
+        if host_arg.is_some() {
+            *host = host_arg.take();
+        }
+        if app_arg.is_some() {
+            *app_name = app_arg.take();
+        }
+    }
+}
+
+/// Replaces an alias passed as `--app-name`, or as `--host` without an app, with the app it stands for.
+/// An explicit `--host` wins over the host of the alias.
+fn expand_app_alias(config: &Config, command: &mut CommandsCli) -> Result<()> {
+    let CommandsCli::Apps { host, app_name, .. } = command else {
+        return Ok(());
+    };
+    let target = match (&host, &app_name) {
+        (_, Some(name)) => config.aliases.get(name),
+        (Some(name), None) => config.aliases.get(name).filter(|t| t.contains(':')),
+        (None, None) => None,
+    };
+    let Some(target) = target else {
+        return Ok(());
+    };
+
+    let (alias_host, alias_app) = split_alias_target(target)?;
+    if app_name.is_none() {
+        *host = None;
+    }
+    *app_name = Some(alias_app.to_owned());
+    if host.is_none() {
+        *host = alias_host.map(str::to_owned);
     }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
-    let cli = Cli::parse();
-    let config = load_config();
+    let mut cli = Cli::parse();
+    merge_positional_app(&mut cli.command);
+    init_logging(cli.verbose);
+    if let Some(path) = &cli.config {
+        override_config_path(path.to_owned());
+    }
+    let mut config = if cli.config.is_some() {
+        load_explicit_config(&cli.command)?
+    } else {
+        load_config(&config_file_path())
+    };
+    if let Some(path) = &cli.cache_dir {
+        config.cache_dir = path.to_owned();
+    }
+    set_cache_dir(config.cache_dir.to_owned());
+    if let CommandsCli::Apps { exclude, .. } = &cli.command {
+        config.ignore_hosts.extend(exclude.iter().cloned());
+    }
+    if let Some(path) = &cli.identity {
+        config.identity_file = Some(path.to_owned());
+    }
+    // The config commands keep working to fix a wrong identity_file, completion never connects
+    if !matches!(
+        cli.command,
+        CommandsCli::Config { .. } | CommandsCli::Complete { .. }
+    ) {
+        check_identity_file(&mut config)?;
+    }
+    expand_app_alias(&config, &mut cli.command)?;
     init_runtime_dirs(&config)?;
 
+    // https://no-color.org: any non-empty value disables colors
+    if cli.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty()) {
+        NO_COLOR.store(true, Ordering::Relaxed);
+    }
+    if cli.quiet || !std::io::stdout().is_terminal() {
+        QUIET.store(true, Ordering::Relaxed);
+    }
+    // Hints would end up in between the json on stdout, spinners draw on stderr
+    let show_hint = config.next_time_use_msg && cli.output == OutputFormat::Human && !quiet();
+    if cli.quiet {
+        QUIET_FLAG.store(true, Ordering::Relaxed);
+        hide_spinners();
+    }
+    if config.prefer_builtin_selector {
+        prefer_builtin_selector();
+    }
+
     let command = Commands::build(&cli.command, &config)?;
 
     match command {
@@ -594,7 +3858,7 @@ fn main() -> Result<()> {
             app_command,
         } => {
             if dry_run {
-                bail!("Not implemented yet");
+                return print_dry_run(&config, &remote_app, &app_command, cli.output);
             }
 
             match &app_command {
@@ -602,133 +3866,484 @@ fn main() -> Result<()> {
                     container_name,
                     host_port,
                     remote_port,
+                    via_published,
+                    background,
+                } => run_container_tunnel(
+                    &config,
+                    &remote_app,
+                    container_name,
+                    *host_port,
+                    *remote_port,
+                    *via_published,
+                    *background,
+                )?,
+                ApplicationCommand::SshSession => attach_ssh_session(&config, &remote_app)?,
+                ApplicationCommand::RetrieveBackup {
+                    backup,
+                    bwlimit,
+                    preview,
+                    dest,
                 } => {
-                    // This is sadly needed because the tunnel command needs Ctrl+C to quit
-                    // Which terminates the program and does not allow us to print to "next time use ..."
-                    // message. Ideally we want to capture Ctrl+C and print the message before exiting
-                    if let CommandsCli::Apps {
-                        refresh: _,
-                        dry_run: _,
-                        host,
-                        app_name,
-                        app_command: app_command_cli,
-                    } = &cli.command
-                    {
-                        // TODO remove code duplication (same code is copied 70 lines donw)
-                        if config.next_time_use_msg {
-                            if host.is_none() || app_name.is_none() {
-                                print_application_command(&remote_app, &app_command);
-                            } else {
-                                match app_command_cli {
-                                    None => print_application_command(&remote_app, &app_command),
-                                    Some(app_command_cli) => {
-                                        if let ApplicationCommandCli::Tunnel {
-                                            container_name,
-                                            host_port,
-                                            remote_port,
-                                        } = app_command_cli
-                                        {
-                                            if container_name.is_none()
-                                                || host_port.is_none()
-                                                || remote_port.is_none()
-                                            {
-                                                print_application_command(
-                                                    &remote_app,
-                                                    &app_command,
-                                                );
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        }
-                    } else {
-                        bail!("Should never happen");
-                    }
-                    run_container_tunnel(
-                        &remote_app.host,
-                        &container_name,
-                        *host_port,
-                        *remote_port,
-                    )?
-                }
-                ApplicationCommand::SshSession => attach_ssh_session(&remote_app)?,
-                ApplicationCommand::RetrieveBackup => {
-                    let root_folder = find_semantic_works_root_folder()?;
+                    let root_folder = retrieve_root_folder(&config, dest.as_deref())?;
                     restore_backup_or_files(
-                        &remote_app.host,
-                        &remote_app.app_name,
+                        &config,
+                        &remote_app,
                         &root_folder,
                         true,
+                        backup.as_deref(),
+                        *bwlimit,
+                        *preview,
                     )?;
                 }
-                ApplicationCommand::RetrieveFiles => {
-                    let root_folder = find_semantic_works_root_folder()?;
+                ApplicationCommand::RetrieveFiles {
+                    bwlimit,
+                    preview,
+                    dest,
+                } => {
+                    let root_folder = retrieve_root_folder(&config, dest.as_deref())?;
                     restore_backup_or_files(
-                        &remote_app.host,
-                        &remote_app.app_name,
+                        &config,
+                        &remote_app,
                         &root_folder,
                         false,
+                        None,
+                        *bwlimit,
+                        *preview,
                     )?;
                 }
-                ApplicationCommand::HostedUrl => {
-                    let yaml = remote_app.retrieve_app_docker_config()?;
-                    let doc: Value = serde_yaml::from_str(&yaml)?;
-                    match get_env(&doc, "identifier", "LETSENCRYPT_HOST") {
-                        Some(url) => {
-                            println!("");
-                            println!("");
-                            println!("The app is hosted at: https://{url}");
-                            println!("");
+                ApplicationCommand::HostedUrl { open } => {
+                    // `docker compose config` already substitutes the variables from the app's .env
+                    let yaml = remote_app.retrieve_app_docker_config(&config)?;
+                    let urls = hosted_urls(&config, &yaml)?;
+                    match urls.as_slice() {
+                        [] => bail!("No URL specified in the docker config"),
+                        [url, ..] if cli.output == OutputFormat::Json => {
+                            let hosted_url = serde_json::json!({
+                                "host": remote_app.host,
+                                "app_name": remote_app.app_name,
+                                "url": url,
+                                "urls": urls,
+                            });
+                            println!("{}", serde_json::to_string_pretty(&hosted_url)?);
                         }
-                        None => bail!("No URL specified in the docker config"),
-                    }
-                }
-            }
-
-            if let CommandsCli::Apps {
-                refresh: _,
-                dry_run: _,
-                host,
-                app_name,
-                app_command: app_command_cli,
-            } = &cli.command
-            {
-                // TODO remove code duplication
-                if config.next_time_use_msg {
-                    if host.is_none() || app_name.is_none() {
-                        print_application_command(&remote_app, &app_command);
-                    } else {
-                        match app_command_cli {
-                            None => print_application_command(&remote_app, &app_command),
-                            Some(app_command_cli) => {
-                                if let ApplicationCommandCli::Tunnel {
-                                    container_name,
-                                    host_port,
-                                    remote_port,
-                                } = app_command_cli
-                                {
-                                    if container_name.is_none()
-                                        || host_port.is_none()
-                                        || remote_port.is_none()
-                                    {
-                                        print_application_command(&remote_app, &app_command);
-                                    }
-                                }
+                        _ if quiet() => {
+                            for url in &urls {
+                                println!("{url}");
+                            }
+                        }
+                        [url] => {
+                            println!();
+                            println!();
+                            println!("The app is hosted at: {url}");
+                            println!();
+                        }
+                        _ => {
+                            println!();
+                            println!();
+                            println!("The app is hosted at:");
+                            for url in &urls {
+                                println!("  {url}");
                             }
+                            println!();
                         }
                     }
+
+                    if *open {
+                        let url = match urls.as_slice() {
+                            [url] => url.to_owned(),
+                            _ => run_fzf(
+                                &urls,
+                                "Choose a URL to open",
+                                Some("The app is hosted on several domains"),
+                                "",
+                                None,
+                            )?
+                            .ok_or_else(|| anyhow!("No URL selected"))?,
+                        };
+                        open_in_browser(&url)?;
+                    }
                 }
-            } else {
-                bail!("Should never happen");
+                ApplicationCommand::Logs {
+                    container_name,
+                    follow,
+                    tail,
+                } => {
+                    logs_command(
+                        &config,
+                        &remote_app,
+                        container_name.as_deref(),
+                        *follow,
+                        *tail,
+                    )
+                    .logged_status()
+                    .map_err(tool_error("ssh"))?;
+                }
+                ApplicationCommand::Restart {
+                    container_name,
+                    yes,
+                } => {
+                    restart_containers(&config, &remote_app, container_name.as_deref(), *yes)?;
+                }
+                ApplicationCommand::Ps => {
+                    show_container_statuses(&config, &remote_app, cli.output)?
+                }
+                ApplicationCommand::Exec {
+                    container_name,
+                    command,
+                } => {
+                    exec_command(&config, &remote_app, container_name, command)
+                        .logged_status()
+                        .map_err(tool_error("ssh"))?;
+                }
+                ApplicationCommand::Env { service, set, .. } if set.is_empty() => {
+                    show_app_env(&config, &remote_app, service.as_deref(), cli.output)?
+                }
+                ApplicationCommand::Env { set, yes, .. } => {
+                    write_app_env(&config, &remote_app, set, *yes)?
+                }
+                ApplicationCommand::Run { command } => {
+                    let status = run_command(&config, &remote_app, command)
+                        .logged_status()
+                        .map_err(tool_error("ssh"))?;
+                    // Pass the remote exit code on so `run` can be used in scripts
+                    if !status.success() {
+                        std::process::exit(status.code().unwrap_or(1));
+                    }
+                }
+            }
+
+            if show_hint && was_prompted(&cli.command) {
+                print_application_command(&remote_app, &app_command);
             }
         }
+        Commands::MultiApps {
+            dry_run,
+            remote_apps,
+            app_command,
+        } => {
+            if dry_run {
+                return print_multi_dry_run(&config, &remote_apps, &app_command, cli.output);
+            }
+
+            run_on_apps(&config, &remote_apps, &app_command, cli.output)?;
+        }
         Commands::Config { command } => match command {
             ConfigCommand::Init => {
                 write_default_config()?;
             }
+            ConfigCommand::Path => print_paths(),
+            ConfigCommand::Show => show_config(&config, cli.output)?,
+            ConfigCommand::SetDefault {
+                host,
+                app_name,
+                container_name,
+                remote_port,
+            } => set_app_default(&host, &app_name, container_name, remote_port)?,
+            ConfigCommand::IgnoreHost { host } => set_host_ignored(&host, true)?,
+            ConfigCommand::UnignoreHost { host } => set_host_ignored(&host, false)?,
+            ConfigCommand::Alias { command } => match command {
+                AliasCommand::Add { name, target } => add_alias(&name, &target)?,
+                AliasCommand::Remove { name } => remove_alias(&name)?,
+            },
+        },
+        Commands::ListApps {
+            no_cache,
+            host,
+            json,
+            format,
+        } => {
+            let output = if json { OutputFormat::Json } else { cli.output };
+            servers_list(
+                &config,
+                no_cache,
+                host.as_deref(),
+                output,
+                format.as_deref(),
+            )?;
+        }
+        Commands::AllHostedUrls { no_cache, host } => {
+            all_hosted_urls(&config, no_cache, host.as_deref(), cli.output)?;
+        }
+        Commands::Cache { command } => match command {
+            CacheCommand::Clear { yes } => clear_servers_cache(yes)?,
+            CacheCommand::Info => show_cache_info(&config, cli.output)?,
+        },
+        Commands::Tunnels { command } => match command {
+            TunnelsCommand::List => list_tunnels(cli.output)?,
+            TunnelsCommand::Stop { port, all } => stop_tunnels(port, all)?,
         },
+        Commands::CheckHosts => check_hosts(&config, cli.output)?,
+        Commands::Doctor => doctor(cli.output)?,
+        Commands::Completions { shell } => {
+            clap_complete::generate(shell, &mut Cli::command(), "rpio", &mut std::io::stdout());
+        }
+        Commands::Preview { line } => print_app_preview(&config, &line)?,
+        Commands::Complete { target } => print_completion_candidates(&config, target),
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn data_folder(path: &str, root: &str) -> DataFolder {
+        DataFolder {
+            path: path.to_owned(),
+            root: root.to_owned(),
+            container: None,
+        }
+    }
+
+    fn picker_cache() -> ServersCache {
+        ServersCache::new(BTreeMap::from([
+            (
+                "alpha".to_owned(),
+                ServerEntry {
+                    last_updated: 0,
+                    host_name: Some("deploy@alpha.example.com:22".to_owned()),
+                    error: None,
+                    data_folders: vec![
+                        data_folder("app:two", DEFAULT_DATA_ROOT),
+                        data_folder("my app", DEFAULT_DATA_ROOT),
+                        data_folder("backoffice", "/srv/apps"),
+                    ],
+                },
+            ),
+            (
+                "beta".to_owned(),
+                ServerEntry {
+                    last_updated: 0,
+                    host_name: None,
+                    error: Some("Connection timed out".to_owned()),
+                    data_folders: Vec::new(),
+                },
+            ),
+        ]))
+    }
+
+    fn assert_picker_round_trip(colors: bool) {
+        NO_COLOR.store(!colors, Ordering::Relaxed);
+        let lines = build_fzf_lines(
+            &Config::default(),
+            &picker_cache(),
+            None,
+            false,
+            PickerSort::Host,
+        );
+        assert_eq!(lines.iter().any(|line| line.contains('\x1b')), colors);
+
+        let mut picked: Vec<(String, Option<u16>, String)> = lines
+            .iter()
+            .filter_map(|line| parse_selection(line).unwrap())
+            .map(|remote_app| {
+                let qualified_name = remote_app.qualified_name();
+                (remote_app.host, remote_app.port, qualified_name)
+            })
+            .collect();
+        picked.sort();
+        assert_eq!(
+            picked,
+            [
+                ("alpha", None, "/srv/apps/backoffice"),
+                ("alpha", None, "app:two"),
+                ("alpha", None, "my app"),
+            ]
+            .map(|(host, port, app)| (host.to_owned(), port, app.to_owned()))
+        );
+    }
+
+    #[test]
+    fn strips_colors_cursor_movement_osc_and_charset_escapes() {
+        assert_eq!(strip_ansi("\x1b[2malpha\x1b[0m"), "alpha");
+        assert_eq!(strip_ansi("\x1b[1A\x1b[2K\x1b[10;20Happ"), "app");
+        assert_eq!(strip_ansi("\x1b]0;title\x07app"), "app");
+        assert_eq!(
+            strip_ansi("\x1b]8;;https://example.com\x1b\\link\x1b]8;;\x1b\\"),
+            "link"
+        );
+        assert_eq!(strip_ansi("\x1b(Bapp\x1b)0"), "app");
+    }
+
+    #[test]
+    fn selection_survives_escape_sequences() {
+        let line = "\x1b]0;rpio\x07\x1b[1Aapp:two:\x1b[2malpha\x1b[0m\x1b(B\talpha\tapp:two";
+        let remote_app = parse_selection(line).unwrap().unwrap();
+        assert_eq!(remote_app.host, "alpha");
+        assert_eq!(remote_app.app_name, "app:two");
+    }
+
+    #[test]
+    fn data_folders_are_sorted_and_deduplicated() {
+        let folders = sorted_data_folders(vec![
+            data_folder("app-two", "/data"),
+            data_folder("app-one", "/srv"),
+            data_folder("app-one", "/data"),
+            data_folder("app-two", "/data"),
+            data_folder("app-one", "/srv"),
+        ]);
+        let directories: Vec<String> = folders.iter().map(DataFolder::directory).collect();
+        assert_eq!(
+            directories,
+            ["/data/app-one", "/srv/app-one", "/data/app-two"]
+        );
+    }
+
+    #[test]
+    fn migrates_version_1_caches() {
+        let v1 = r#"
+[servers.alpha]
+last_updated = 1700000000
+data_folders = [{ path = "app-one" }, { path = "app-two", container = "app-two-db-1" }]
+
+[servers.beta]
+last_updated = 1700000000
+error = "Connection timed out"
+data_folders = []
+"#;
+        let config = Config {
+            data_roots: BTreeMap::from([("alpha".to_owned(), vec!["/srv".to_owned()])]),
+            ..Config::default()
+        };
+        let (cache, version) = parse_servers_cache(&config, Path::new("servers.toml"), v1).unwrap();
+        assert_eq!(version, 1);
+        assert_eq!(cache.version, SERVERS_CACHE_VERSION);
+
+        let folders = &cache.servers["alpha"].data_folders;
+        let directories: Vec<String> = folders.iter().map(DataFolder::directory).collect();
+        assert_eq!(directories, ["/srv/app-one", "/srv/app-two"]);
+        assert_eq!(folders[1].container.as_deref(), Some("app-two-db-1"));
+        assert_eq!(
+            cache.servers["beta"].error.as_deref(),
+            Some("Connection timed out")
+        );
+    }
+
+    #[test]
+    fn newer_caches_are_rejected() {
+        let newer = format!("version = {}\n", SERVERS_CACHE_VERSION + 1);
+        assert!(
+            parse_servers_cache(&Config::default(), Path::new("servers.toml"), &newer).is_err()
+        );
+    }
+
+    fn remote_command(command: &Command) -> String {
+        command
+            .get_args()
+            .last()
+            .unwrap()
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    #[test]
+    fn app_directories_are_quoted_for_the_remote_shell() {
+        let config = Config::default();
+        let remote_app = RemoteApp::from_host_and_app("alpha", "my app").unwrap();
+        assert_eq!(
+            remote_command(&ssh_session_command(&config, &remote_app)),
+            "cd '/data/my app' ; bash --login"
+        );
+        assert_eq!(
+            remote_command(&run_command(&config, &remote_app, &["ls".to_owned()])),
+            "cd '/data/my app' && ls"
+        );
+        assert_eq!(
+            remote_command(&read_env_file_command(&config, &remote_app)),
+            "cat '/data/my app/.env' 2>/dev/null || true"
+        );
+        assert_eq!(
+            remote_command(&write_env_file_command(&config, &remote_app)),
+            "cat > '/data/my app/.env'"
+        );
+        for command in [
+            logs_command(&config, &remote_app, None, false, None),
+            restart_command(&config, &remote_app, None),
+            exec_command(&config, &remote_app, "db", &[]),
+            remote_app.ps_command(&config, true),
+            remote_app.docker_config_command(&config),
+        ] {
+            assert!(remote_command(&command).starts_with("cd '/data/my app' && "));
+        }
+    }
+
+    #[test]
+    fn plain_app_directories_stay_readable() {
+        let remote_app = RemoteApp::from_host_and_app("alpha", "app-one").unwrap();
+        assert_eq!(
+            remote_command(&ssh_session_command(&Config::default(), &remote_app)),
+            "cd /data/app-one ; bash --login"
+        );
+    }
+
+    fn parsed_command(args: &[&str]) -> CommandsCli {
+        let mut cli = Cli::try_parse_from(["rpio"].iter().chain(args)).unwrap();
+        merge_positional_app(&mut cli.command);
+        cli.command
+    }
+
+    #[test]
+    fn no_hint_when_everything_was_given() {
+        for args in [
+            &["apps", "--host", "alpha", "--app-name", "app-one", "ps"][..],
+            &["apps", "alpha", "app-one", "ssh-session"],
+            &["apps", "alpha", "app-one", "logs", "--container-name", "db"],
+            &["apps", "alpha", "app-one", "run", "ls"],
+            &[
+                "apps",
+                "alpha",
+                "app-one",
+                "tunnel",
+                "--container-name",
+                "db",
+                "--host-port",
+                "5432",
+                "--remote-port",
+                "5432",
+            ],
+            &["cache", "info"],
+        ] {
+            assert!(!was_prompted(&parsed_command(args)), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn hint_when_something_was_prompted() {
+        for args in [
+            &["apps"][..],
+            &["apps", "--host", "alpha", "ps"],
+            &["apps", "--app-name", "app-one", "ps"],
+            &["apps", "alpha", "app-one"],
+            &["apps", "alpha", "app-one", "logs"],
+            &[
+                "apps",
+                "alpha",
+                "app-one",
+                "tunnel",
+                "--container-name",
+                "db",
+            ],
+        ] {
+            assert!(was_prompted(&parsed_command(args)), "{args:?}");
+        }
+    }
+
+    #[test]
+    fn picker_leaves_out_ignored_hosts() {
+        let config = Config {
+            ignore_hosts: vec!["alp*".to_owned()],
+            ..Config::default()
+        };
+        let lines = build_fzf_lines(&config, &picker_cache(), None, false, PickerSort::Host);
+        assert_eq!(lines.len(), 1);
+        assert!(strip_ansi(&lines[0]).starts_with(&format!("{UNREACHABLE_MARKER} beta")));
+    }
+
+    #[test]
+    fn picker_lines_round_trip() {
+        // One test for both, the color switch is process wide
+        assert_picker_round_trip(true);
+        assert_picker_round_trip(false);
+    }
+}
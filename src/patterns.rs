@@ -0,0 +1,44 @@
+use anyhow::{Context, Result};
+use globset::{Glob, GlobSet, GlobSetBuilder};
+
+/// Compiles glob patterns like `db-*` or `*.staging`, plain names keep matching exactly
+pub fn build_glob_set(patterns: &[String]) -> Result<GlobSet> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        let glob = Glob::new(pattern).with_context(|| format!("Invalid pattern '{pattern}'"))?;
+        builder.add(glob);
+    }
+
+    Ok(builder.build()?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn patterns(patterns: &[&str]) -> GlobSet {
+        build_glob_set(&patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>()).unwrap()
+    }
+
+    #[test]
+    fn matches_globs_and_exact_names() {
+        let set = patterns(&["db-*", "*.staging", "bastion"]);
+        for host in ["db-1", "db-primary", "app.staging", "bastion"] {
+            assert!(set.is_match(host), "{host} should match");
+        }
+        for host in ["db", "mydb-1", "app.staging.eu", "bastion-2", "production"] {
+            assert!(!set.is_match(host), "{host} should not match");
+        }
+    }
+
+    #[test]
+    fn no_patterns_match_nothing() {
+        assert!(!patterns(&[]).is_match("alpha"));
+    }
+
+    #[test]
+    fn invalid_patterns_fail() {
+        let error = build_glob_set(&["db-[".to_owned()]).unwrap_err();
+        assert!(error.to_string().contains("db-["));
+    }
+}
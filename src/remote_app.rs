@@ -1,60 +1,310 @@
 use anyhow::Result;
 use anyhow::anyhow;
+use anyhow::bail;
 use std::process::Command;
 use std::str::FromStr;
+use serde::{Deserialize, Serialize};
+use crate::{COMPOSE_FILE_NAMES, Config};
+use crate::container_cache::{cached_containers, store_containers};
+use crate::shell::{LoggedCommand, quote};
 use crate::spinner::create_and_start_spinner;
+use crate::ssh::{ssh_args, ssh_command};
+use crate::tools::tool_error;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct ContainerStatus {
+    pub name: String,
+    pub state: String,
+    #[serde(default)]
+    pub publishers: Vec<Publisher>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Publisher {
+    #[serde(rename = "URL", default)]
+    pub url: String,
+    pub target_port: u16,
+    pub published_port: u16,
+    pub protocol: String,
+}
+
+impl ContainerStatus {
+    pub fn ports(&self) -> String {
+        self.publishers
+            .iter()
+            .map(|p| {
+                if p.published_port == 0 {
+                    format!("{}/{}", p.target_port, p.protocol)
+                } else {
+                    format!("{}:{}->{}/{}", p.url, p.published_port, p.target_port, p.protocol)
+                }
+            })
+            .collect::<Vec<String>>()
+            .join(", ")
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Container {
+    pub name: String,
+    pub state: String,
+}
+
+impl Container {
+    pub fn is_running(&self) -> bool {
+        self.state == "running"
+    }
+}
 
 #[derive(Clone)]
 pub struct RemoteApp {
     pub host: String,
+    pub port: Option<u16>,
     pub app_name: String,
+    pub root: Option<String>, // `None` means the host's default data root
 }
 
 impl RemoteApp {
     pub fn new(host: String, app_name: String) -> Self {
-        RemoteApp { host, app_name }
+        RemoteApp {
+            host,
+            port: None,
+            app_name,
+            root: None,
+        }
+    }
+
+    /// Splits off the root of an app outside of the default data root, written as `/root/app_name`
+    pub fn from_qualified_name(host: &str, port: Option<u16>, qualified_name: &str) -> Self {
+        let (root, app_name) = match qualified_name.rsplit_once('/') {
+            Some(("", app_name)) => (Some("/"), app_name),
+            Some((root, app_name)) => (Some(root), app_name),
+            None => (None, qualified_name),
+        };
+
+        RemoteApp {
+            host: host.to_string(),
+            port,
+            app_name: app_name.to_string(),
+            root: root.map(|root| root.to_string()),
+        }
+    }
+
+    /// Host (`host[:port]`) and app given separately, so app names may contain colons
+    pub fn from_host_and_app(host: &str, qualified_name: &str) -> Result<Self> {
+        let (host, port) = match host.split_once(':') {
+            Some((host, port)) => {
+                let port = port
+                    .parse::<u16>()
+                    .map_err(|_| anyhow!("Invalid port '{}' in '{}:{}'", port, host, port))?;
+                (host, Some(port))
+            }
+            None => (host, None),
+        };
+        if host.is_empty() || qualified_name.is_empty() {
+            bail!("Both a host and an app name are needed, got '{host}' and '{qualified_name}'");
+        }
+
+        Ok(RemoteApp::from_qualified_name(host, port, qualified_name))
+    }
+
+    /// App name as it was passed on the command line, prefixed with its root if one was given
+    pub fn qualified_name(&self) -> String {
+        match &self.root {
+            Some(root) => format!("{}/{}", root.trim_end_matches('/'), self.app_name),
+            None => self.app_name.to_owned(),
+        }
+    }
+
+    /// Host as it was passed on the command line, including the port if one was given
+    pub fn host_with_port(&self) -> String {
+        match self.port {
+            Some(port) => format!("{}:{}", self.host, port),
+            None => self.host.to_owned(),
+        }
     }
 
-    pub fn fetch_containers(&self) -> Result<Vec<String>> {
+    /// `ssh` command targeting this app's host, the remote command still needs to be added
+    pub fn ssh_command(&self, config: &Config) -> Command {
+        ssh_command(config, &self.host, self.port)
+    }
+
+    /// Remote shell to pass to `rsync -e`, rsync splits it on spaces but keeps quoted arguments together
+    pub fn rsync_remote_shell(&self, config: &Config) -> String {
+        let mut shell = vec!["ssh".to_owned()];
+        shell.extend(ssh_args(config, &self.host, self.port).iter().map(|arg| quote(arg)));
+        shell.join(" ")
+    }
+
+    /// Containers of the app, reused for `container_cache_ttl_seconds` between runs.
+    /// Without `include_stopped` only running containers are listed, like `docker compose ps` does.
+    pub fn fetch_containers(&self, config: &Config, include_stopped: bool) -> Result<Vec<Container>> {
+        let all = if include_stopped { " -a" } else { "" };
+        let cache_key = format!("{}:{}{all}", self.host_with_port(), self.remote_directory(config));
+        if let Some(containers) = cached_containers(&cache_key, config.container_cache_ttl_seconds) {
+            return Ok(containers);
+        }
+
         let spinner = create_and_start_spinner(&format!(
             "Fetching containers for host: {} and app: {}",
             &self.host, &self.app_name
         ));
-        let mut command = Command::new("ssh");
-        command.arg(&self.host).arg(format!(
-            "cd /data/{} && docker compose ps --format {{{{.Names}}}}",
-            &self.app_name
+        let mut command = self.ssh_command(config);
+        command.arg(format!(
+            "cd {} && {} ps{all} --format '{{{{.Names}}}} {{{{.State}}}}'",
+            quote(&self.remote_directory(config)),
+            docker_compose()
         ));
 
-        let output = command.output()?;
+        let output = command.logged_output().map_err(tool_error("ssh"))?;
 
         spinner.finish();
 
+        if !output.status.success() {
+            bail!(
+                "Could not list the containers of {} on {}: {} ({})",
+                self.app_name,
+                self.host,
+                String::from_utf8_lossy(&output.stderr).trim(),
+                output.status
+            );
+        }
+
+        let containers: Vec<Container> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .filter_map(|line| {
+                let (name, state) = line.split_once(' ')?;
+                Some(Container {
+                    name: name.to_owned(),
+                    state: state.to_owned(),
+                })
+            })
+            .collect();
+        if !containers.is_empty() {
+            store_containers(&cache_key, &containers)?;
+        }
+
+        Ok(containers)
+    }
+
+    /// Backup file names of the app, newest first
+    pub fn fetch_backups(&self, config: &Config) -> Result<Vec<String>> {
+        let spinner = create_and_start_spinner(&format!("Fetching backups for {}", &self.app_name));
+        let mut command = self.ssh_command(config);
+        command.arg(format!(
+            "ls -1t {}",
+            quote(&format!("{}/data/db/backups", self.remote_directory(config)))
+        ));
+
+        let output = command.logged_output().map_err(tool_error("ssh"))?;
+
+        spinner.finish();
+
+        if !output.status.success() {
+            bail!(
+                "Could not list the backups of {}: {} ({})",
+                self.app_name,
+                String::from_utf8_lossy(&output.stderr).trim(),
+                output.status
+            );
+        }
+
         Ok(String::from_utf8_lossy(&output.stdout)
             .lines()
+            .filter(|l| !l.trim().is_empty())
             .map(|x| x.to_owned())
             .collect())
     }
 
-    pub fn retrieve_app_docker_config(&self) -> Result<String> {
+    /// `None` when the remote docker compose is too old to support `--format json`
+    pub fn fetch_container_statuses(&self, config: &Config) -> Result<Option<Vec<ContainerStatus>>> {
+        let spinner = create_and_start_spinner(&format!("Fetching container status for {}", &self.app_name));
+        let output = self.ps_command(config, true).logged_output().map_err(tool_error("ssh"))?;
+
+        spinner.finish();
+
+        if !output.status.success() {
+            return Ok(None);
+        }
+
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let stdout = stdout.trim();
+        // Older compose versions print a single array, newer ones print one object per line
+        let statuses = if stdout.starts_with('[') {
+            serde_json::from_str(stdout)
+        } else {
+            stdout.lines().map(serde_json::from_str).collect()
+        };
+
+        Ok(statuses.ok())
+    }
+
+    pub fn ps_command(&self, config: &Config, json: bool) -> Command {
+        let format = if json { " --format json" } else { "" };
+        let mut command = self.ssh_command(config);
+        command.arg(format!(
+            "cd {} && {} ps{format}",
+            quote(&self.remote_directory(config)),
+            docker_compose()
+        ));
+        command
+    }
+
+    pub fn retrieve_app_docker_config(&self, config: &Config) -> Result<String> {
         let spinner =
             create_and_start_spinner(&format!("Fetching docker config for {}", &self.app_name));
-        let output = Command::new("ssh")
-            .arg(&self.host)
-            .arg(format!(
-                "cd {} && docker compose config",
-                self.remote_directory()
-            ))
-            .output()?;
+        let output = self.docker_config_command(config).logged_output().map_err(tool_error("ssh"))?;
 
         spinner.finish();
 
         Ok(String::from_utf8(output.stdout)?)
     }
 
-    fn remote_directory(&self) -> String {
-        format!("/data/{}", self.app_name)
+    pub fn docker_config_command(&self, config: &Config) -> Command {
+        let mut command = self.ssh_command(config);
+        command.arg(format!(
+            "cd {} && {} config",
+            quote(&self.remote_directory(config)),
+            docker_compose()
+        ));
+        command
     }
+
+    pub fn remote_directory(&self, config: &Config) -> String {
+        let root = match &self.root {
+            Some(root) => root,
+            None => config.default_data_root(&self.host),
+        };
+        format!("{}/{}", root.trim_end_matches('/'), self.app_name)
+    }
+}
+
+/// `docker compose` to run in an app's folder. Without one of the default compose files the single
+/// `*compose*.yml` in the folder is passed with `-f`, with its `.override.yml` if there is one, unless
+/// `COMPOSE_FILE` already picks the files. Several candidates fail instead of being merged.
+pub fn docker_compose() -> String {
+    let defaults: Vec<String> = COMPOSE_FILE_NAMES
+        .iter()
+        .map(|name| format!("[ -f {name} ]"))
+        .collect();
+    let pick_file = concat!(
+        r"base=$(ls -1 | grep -E '^(docker-)?compose.*\.ya?ml$' | grep -v '\.override\.'); ",
+        r#"case $(printf '%s' "$base" | grep -c .) in "#,
+        "0) ;; ",
+        r#"1) printf '%s %s' -f "$base"; "#,
+        r#"override=$(printf '%s' "$base" | sed -E 's/\.(ya?ml)$/.override.\1/'); "#,
+        r#"if [ -f "$override" ]; then printf ' %s %s' -f "$override"; fi ;; "#,
+        r#"*) echo "Several compose files in $PWD:" $base", set COMPOSE_FILE in .env to pick one" >&2; exit 1 ;; "#,
+        "esac",
+    );
+    format!(
+        "compose_files=$(if {} || [ -n \"$COMPOSE_FILE\" ] || grep -qs '^COMPOSE_FILE=' .env; then :; else {pick_file}; fi) && docker compose $compose_files",
+        defaults.join(" || ")
+    )
 }
 
 impl FromStr for RemoteApp {
@@ -63,11 +313,139 @@ impl FromStr for RemoteApp {
     fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
         let (app_name, host) = s
             .split_once(':')
-            .ok_or_else(|| anyhow!("Invalid format '{}': expected 'host:app_name'", s))?;
+            .ok_or_else(|| anyhow!("Invalid format '{}': expected 'app_name:host[:port]'", s))?;
+        if app_name.is_empty() || host.is_empty() {
+            bail!("Invalid format '{}': expected 'app_name:host[:port]'", s);
+        }
 
-        Ok(RemoteApp {
-            host: host.to_string(),
-            app_name: app_name.to_string(),
-        })
+        RemoteApp::from_host_and_app(host, app_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    /// Runs `docker_compose()` in a folder with `files`, a fake `docker` prints the arguments it gets
+    fn compose_args(files: &[(&str, &str)]) -> Result<String> {
+        let dir = tempfile::tempdir()?;
+        let bin = dir.path().join("bin");
+        fs::create_dir(&bin)?;
+        fs::write(bin.join("docker"), "#!/bin/sh\necho \"$@\"\n")?;
+        fs::set_permissions(bin.join("docker"), fs::Permissions::from_mode(0o755))?;
+        let app = dir.path().join("app");
+        fs::create_dir(&app)?;
+        for (file, contents) in files {
+            fs::write(app.join(file), contents)?;
+        }
+
+        let path = format!("{}:{}", bin.display(), std::env::var("PATH").unwrap_or_default());
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("{} config", docker_compose()))
+            .current_dir(&app)
+            .env("PATH", path)
+            .env_remove("COMPOSE_FILE")
+            .output()?;
+        if !output.status.success() {
+            bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).trim().to_owned())
+    }
+
+    #[test]
+    fn default_compose_files_are_left_to_docker() {
+        let args = compose_args(&[("docker-compose.yml", ""), ("docker-compose.prod.yml", "")]);
+        assert_eq!(args.unwrap(), "compose config");
+    }
+
+    #[test]
+    fn a_single_nonstandard_file_is_passed_with_its_override() {
+        assert_eq!(
+            compose_args(&[("docker-compose.prod.yml", "")]).unwrap(),
+            "compose -f docker-compose.prod.yml config"
+        );
+        assert_eq!(
+            compose_args(&[
+                ("compose.prod.yaml", ""),
+                ("compose.prod.override.yaml", ""),
+                ("README.md", ""),
+            ])
+            .unwrap(),
+            "compose -f compose.prod.yaml -f compose.prod.override.yaml config"
+        );
+    }
+
+    #[test]
+    fn several_nonstandard_files_are_not_merged() {
+        let error = compose_args(&[
+            ("docker-compose.dev.yml", ""),
+            ("docker-compose.prod.yml", ""),
+            ("docker-compose.prod.override.yml", ""),
+        ])
+        .unwrap_err()
+        .to_string();
+        assert!(error.contains("docker-compose.dev.yml docker-compose.prod.yml"), "{error}");
+        assert!(error.contains("COMPOSE_FILE"), "{error}");
+    }
+
+    #[test]
+    fn compose_file_in_env_is_left_to_docker() {
+        let args = compose_args(&[
+            ("docker-compose.dev.yml", ""),
+            ("docker-compose.prod.yml", ""),
+            (".env", "COMPOSE_FILE=docker-compose.prod.yml\n"),
+        ]);
+        assert_eq!(args.unwrap(), "compose config");
+    }
+
+    #[test]
+    fn parses_app_and_host() {
+        let remote_app: RemoteApp = "app-one:alpha".parse().unwrap();
+        assert_eq!(remote_app.host, "alpha");
+        assert_eq!(remote_app.port, None);
+        assert_eq!(remote_app.app_name, "app-one");
+    }
+
+    #[test]
+    fn parses_a_port_suffix() {
+        let remote_app: RemoteApp = "app-one:alpha:2222".parse().unwrap();
+        assert_eq!(remote_app.host, "alpha");
+        assert_eq!(remote_app.port, Some(2222));
+        assert_eq!(remote_app.host_with_port(), "alpha:2222");
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        for input in ["app-one", ":alpha", "app-one:", "app-one:alpha:notaport", "app-one:alpha:"] {
+            assert!(input.parse::<RemoteApp>().is_err(), "{input} should not parse");
+        }
+    }
+
+    #[test]
+    fn app_names_may_contain_colons() {
+        let remote_app = RemoteApp::from_host_and_app("alpha", "app:two").unwrap();
+        assert_eq!(remote_app.host, "alpha");
+        assert_eq!(remote_app.port, None);
+        assert_eq!(remote_app.app_name, "app:two");
+        assert_eq!(remote_app.root, None);
+    }
+
+    #[test]
+    fn app_names_may_contain_spaces() {
+        let remote_app = RemoteApp::from_host_and_app("alpha:2222", "/srv/my app").unwrap();
+        assert_eq!(remote_app.host, "alpha");
+        assert_eq!(remote_app.port, Some(2222));
+        assert_eq!(remote_app.app_name, "my app");
+        assert_eq!(remote_app.root.as_deref(), Some("/srv"));
+    }
+
+    #[test]
+    fn host_and_app_are_both_required() {
+        assert!(RemoteApp::from_host_and_app("", "app").is_err());
+        assert!(RemoteApp::from_host_and_app("alpha", "").is_err());
+        assert!(RemoteApp::from_host_and_app("alpha:notaport", "app").is_err());
     }
 }
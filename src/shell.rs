@@ -0,0 +1,78 @@
+use log::{debug, trace};
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+
+pub fn format_command(command: &Command) -> String {
+    let program = command.get_program().to_string_lossy();
+    let args = command.get_args().map(|arg| quote(&arg.to_string_lossy()));
+
+    std::iter::once(quote(&program))
+        .chain(args)
+        .collect::<Vec<String>>()
+        .join(" ")
+}
+
+/// The program followed by its arguments, as they would be passed to `exec`
+pub fn command_argv(command: &Command) -> Vec<String> {
+    std::iter::once(command.get_program())
+        .chain(command.get_args())
+        .map(|arg| arg.to_string_lossy().into_owned())
+        .collect()
+}
+
+pub fn quote(arg: &str) -> String {
+    let is_safe = !arg.is_empty()
+        && arg
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./:=@,+%".contains(c));
+
+    if is_safe {
+        arg.to_owned()
+    } else {
+        format!("'{}'", arg.replace('\'', r"'\''"))
+    }
+}
+
+/// Runs commands like their `Command` counterparts, logging the command line and exit status
+pub trait LoggedCommand {
+    fn logged_output(&mut self) -> io::Result<Output>;
+    fn logged_status(&mut self) -> io::Result<ExitStatus>;
+    fn logged_spawn(&mut self) -> io::Result<Child>;
+}
+
+impl LoggedCommand for Command {
+    fn logged_output(&mut self) -> io::Result<Output> {
+        debug!("Running {}", format_command(self));
+        let output = self.output()?;
+        debug!(
+            "{} exited with {}",
+            self.get_program().display(),
+            output.status
+        );
+        if !output.stderr.is_empty() {
+            trace!(
+                "stderr: {}",
+                String::from_utf8_lossy(&output.stderr).trim_end()
+            );
+        }
+        Ok(output)
+    }
+
+    fn logged_status(&mut self) -> io::Result<ExitStatus> {
+        debug!("Running {}", format_command(self));
+        let status = self.status()?;
+        debug!("{} exited with {status}", self.get_program().display());
+        Ok(status)
+    }
+
+    fn logged_spawn(&mut self) -> io::Result<Child> {
+        debug!("Spawning {}", format_command(self));
+        let child = self.spawn()?;
+        debug!(
+            "{} started with pid {}",
+            self.get_program().display(),
+            child.id()
+        );
+        Ok(child)
+    }
+}
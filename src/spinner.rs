@@ -1,17 +1,56 @@
 use indicatif::ProgressBar;
+use indicatif::ProgressDrawTarget;
 use indicatif::ProgressFinish;
 use indicatif::ProgressStyle;
+use std::io::IsTerminal;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::time::Duration;
 
+static SPINNERS_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Makes every spinner created afterwards a no-op
+pub fn hide_spinners() {
+    SPINNERS_HIDDEN.store(true, Ordering::Relaxed);
+}
+
 pub fn create_and_start_spinner(message: &str) -> ProgressBar {
-    let style = ProgressStyle::with_template("{spinner} {msg}")
+    start_spinner(message, "{spinner} {msg}")
+}
+
+/// Also shows how long the operation has been running, for indexing and transfers that can take minutes
+pub fn create_and_start_timed_spinner(message: &str) -> ProgressBar {
+    start_spinner(message, "{spinner} {msg} ({elapsed})")
+}
+
+fn start_spinner(message: &str, template: &str) -> ProgressBar {
+    // Spinner frames would end up as garbage in redirected output and log files
+    if SPINNERS_HIDDEN.load(Ordering::Relaxed) || !std::io::stderr().is_terminal() {
+        return ProgressBar::hidden();
+    }
+
+    let style = ProgressStyle::with_template(template)
         .unwrap()
         .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"]);
 
-    let bar = ProgressBar::new_spinner()
+    // Drawn on stderr so stdout only carries results and can be piped
+    let bar = ProgressBar::with_draw_target(None, ProgressDrawTarget::stderr())
         .with_style(style)
         .with_message(message.to_owned())
         .with_finish(ProgressFinish::WithMessage("✔ Done".into()));
     bar.enable_steady_tick(Duration::from_millis(100));
     bar
 }
+
+/// Turns a spinner into a progress bar, can be called repeatedly with updated values
+pub fn set_progress(bar: &ProgressBar, percent: u64, message: &str) {
+    if bar.length().is_none() {
+        let style = ProgressStyle::with_template("{spinner} {msg} [{bar:30}] {pos}% ({elapsed})")
+            .unwrap()
+            .tick_strings(&["⠋", "⠙", "⠹", "⠸", "⠼", "⠴", "⠦", "⠧", "⠇", "⠏"])
+            .progress_chars("=> ");
+        bar.set_style(style);
+        bar.set_length(100);
+    }
+    bar.set_position(percent.min(100));
+    bar.set_message(message.to_owned());
+}
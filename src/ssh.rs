@@ -0,0 +1,32 @@
+use crate::Config;
+use std::process::Command;
+
+/// Options shared by every ssh invocation, `port` overrides the port from the ssh config.
+/// The options for `host` come before the global `ssh_options` because ssh uses the first value it gets.
+pub fn ssh_args(config: &Config, host: &str, port: Option<u16>) -> Vec<String> {
+    let mut args = vec![
+        "-o".to_owned(),
+        format!("ConnectTimeout={}", config.connect_timeout_seconds),
+    ];
+    let host_options = config.host_ssh_options.get(host).into_iter().flatten();
+    for option in host_options.chain(&config.ssh_options) {
+        args.extend(["-o".to_owned(), option.to_owned()]);
+    }
+    if let Some(identity_file) = &config.identity_file {
+        args.extend([
+            "-i".to_owned(),
+            identity_file.to_string_lossy().into_owned(),
+        ]);
+    }
+    if let Some(port) = port {
+        args.extend(["-p".to_owned(), port.to_string()]);
+    }
+    args
+}
+
+/// `ssh` command targeting `host`, the remote command still needs to be added
+pub fn ssh_command(config: &Config, host: &str, port: Option<u16>) -> Command {
+    let mut command = Command::new("ssh");
+    command.args(ssh_args(config, host, port)).arg(host);
+    command
+}
@@ -0,0 +1,353 @@
+use anyhow::Result;
+use anyhow::anyhow;
+use anyhow::bail;
+use globset::Glob;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub struct SshHost {
+    pub alias: String,
+    pub host_name: Option<String>,
+    pub user: Option<String>,
+    pub port: Option<u16>,
+}
+
+impl SshHost {
+    fn new(alias: &str) -> Self {
+        SshHost {
+            alias: alias.to_owned(),
+            host_name: None,
+            user: None,
+            port: None,
+        }
+    }
+
+    /// `user@hostname:port` as ssh will resolve it, `None` if no `HostName` was configured
+    pub fn resolved_name(&self) -> Option<String> {
+        let host_name = self.host_name.as_ref()?;
+        let mut name = host_name.to_owned();
+        if let Some(user) = &self.user {
+            name = format!("{user}@{name}");
+        }
+        if let Some(port) = self.port {
+            name = format!("{name}:{port}");
+        }
+        Some(name)
+    }
+}
+
+pub fn ssh_config_path() -> Result<PathBuf> {
+    let home =
+        dirs::home_dir().ok_or_else(|| anyhow!("Could not determine your home directory"))?;
+    Ok(home.join(".ssh/config"))
+}
+
+/// Hosts from `~/.ssh/config` and the files it includes, empty when it does not exist yet
+pub fn read_ssh_hosts() -> Result<Vec<SshHost>> {
+    read_ssh_hosts_from(ssh_config_path()?)
+}
+
+/// `~/` in includes is resolved against the folder above the one `path` is in
+fn read_ssh_hosts_from(path: PathBuf) -> Result<Vec<SshHost>> {
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            eprintln!(
+                "Warning: {} does not exist, add the hosts you deploy to there",
+                path.display()
+            );
+            return Ok(Vec::new());
+        }
+        Err(e) => bail!("Could not read {} to find hosts: {e}", path.display()),
+    };
+
+    let mut parser = SshConfigParser {
+        ssh_dir: path.parent().map(Path::to_path_buf).unwrap_or_default(),
+        hosts: Vec::new(),
+        current: Vec::new(),
+        including: vec![path.canonicalize().unwrap_or(path)],
+    };
+    parser.parse(&contents)?;
+    parser.hosts.append(&mut parser.current);
+
+    Ok(parser.hosts)
+}
+
+/// ssh gives up on configs that nest `Include`s deeper than this
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+struct SshConfigParser {
+    /// Relative `Include` paths are resolved against `~/.ssh`
+    ssh_dir: PathBuf,
+    hosts: Vec<SshHost>,
+    /// Hosts declared by the `Host` line the parser is in
+    current: Vec<SshHost>,
+    /// Files that are being parsed, to skip includes that would loop
+    including: Vec<PathBuf>,
+}
+
+impl SshConfigParser {
+    /// Included files are parsed in place, like ssh does
+    fn parse(&mut self, contents: &str) -> Result<()> {
+        for line in contents.lines() {
+            let Some((keyword, value)) = split_keyword(line) else {
+                continue;
+            };
+
+            match keyword.to_ascii_lowercase().as_str() {
+                "include" => {
+                    for pattern in value.split_whitespace() {
+                        self.include(pattern)?;
+                    }
+                }
+                // `Host a b` declares both aliases, wildcard patterns such as `*` are not real hosts
+                "host" => {
+                    self.hosts.append(&mut self.current);
+                    self.current = value
+                        .split_whitespace()
+                        .filter(|alias| !is_pattern(alias))
+                        .map(SshHost::new)
+                        .collect();
+                }
+                // Options after a `Match` block do not belong to the previous hosts
+                "match" => self.hosts.append(&mut self.current),
+                "hostname" => {
+                    for host in &mut self.current {
+                        host.host_name = Some(value.to_owned());
+                    }
+                }
+                "user" => {
+                    for host in &mut self.current {
+                        host.user = Some(value.to_owned());
+                    }
+                }
+                "port" => {
+                    for host in &mut self.current {
+                        host.port = value.parse().ok();
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+
+    fn include(&mut self, pattern: &str) -> Result<()> {
+        let pattern = match pattern.strip_prefix("~/") {
+            Some(rest) => self.ssh_dir.parent().unwrap_or(&self.ssh_dir).join(rest),
+            None => self.ssh_dir.join(pattern),
+        };
+
+        for path in expand_glob(&pattern) {
+            let path = path.canonicalize().unwrap_or(path);
+            if self.including.contains(&path) {
+                eprintln!("Warning: skipping {}, it includes itself", path.display());
+                continue;
+            }
+            if self.including.len() >= MAX_INCLUDE_DEPTH {
+                bail!(
+                    "Too many nested Include directives in your ssh config at {}",
+                    path.display()
+                );
+            }
+
+            // Like ssh, patterns that match nothing and unreadable files are skipped
+            let Ok(contents) = fs::read_to_string(&path) else {
+                continue;
+            };
+            self.including.push(path);
+            self.parse(&contents)?;
+            self.including.pop();
+        }
+
+        Ok(())
+    }
+}
+
+/// Files matching `pattern`, sorted per folder, wildcards may appear in any component
+fn expand_glob(pattern: &Path) -> Vec<PathBuf> {
+    let mut paths = vec![PathBuf::new()];
+    for component in pattern.components() {
+        let component = component.as_os_str();
+        let Some(glob) = component
+            .to_str()
+            .filter(|c| is_pattern(c))
+            .and_then(|c| Glob::new(c).ok())
+        else {
+            for path in &mut paths {
+                path.push(component);
+            }
+            continue;
+        };
+
+        let matcher = glob.compile_matcher();
+        paths = paths
+            .iter()
+            .flat_map(|dir| {
+                let mut entries: Vec<PathBuf> = fs::read_dir(dir)
+                    .into_iter()
+                    .flatten()
+                    .flatten()
+                    .filter(|entry| matcher.is_match(entry.file_name()))
+                    .map(|entry| entry.path())
+                    .collect();
+                entries.sort();
+                entries
+            })
+            .collect();
+    }
+
+    paths
+}
+
+/// Splits a config line in its keyword and value, both `Key value` and `Key=value` are allowed.
+/// A `#` at the start of the line or after whitespace starts a comment.
+fn split_keyword(line: &str) -> Option<(&str, &str)> {
+    let line = match line
+        .char_indices()
+        .find(|&(i, c)| c == '#' && line[..i].ends_with(char::is_whitespace))
+    {
+        Some((comment, _)) => &line[..comment],
+        None => line,
+    };
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let (keyword, value) = line.split_once(|c: char| c.is_whitespace() || c == '=')?;
+    let value = value.trim_start_matches(|c: char| c.is_whitespace() || c == '=');
+
+    Some((keyword, value.trim()))
+}
+
+fn is_pattern(alias: &str) -> bool {
+    alias.contains(['*', '?', '!'])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    /// A home folder with `.ssh/config` and the given files, paths relative to `.ssh`
+    fn ssh_tree(files: &[(&str, &str)]) -> TempDir {
+        let home = tempfile::tempdir().unwrap();
+        for (path, contents) in files {
+            let path = home.path().join(".ssh").join(path);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(path, contents).unwrap();
+        }
+        home
+    }
+
+    fn aliases(home: &TempDir) -> Result<Vec<String>> {
+        let hosts = read_ssh_hosts_from(home.path().join(".ssh/config"))?;
+        Ok(hosts.into_iter().map(|host| host.alias).collect())
+    }
+
+    fn parse(contents: &str) -> Vec<SshHost> {
+        let home = ssh_tree(&[("config", contents)]);
+        read_ssh_hosts_from(home.path().join(".ssh/config")).unwrap()
+    }
+
+    #[test]
+    fn splits_keywords_and_values() {
+        assert_eq!(
+            split_keyword("HostName alpha.example.com"),
+            Some(("HostName", "alpha.example.com"))
+        );
+        assert_eq!(split_keyword("  Port=2222"), Some(("Port", "2222")));
+        assert_eq!(split_keyword("User = deploy"), Some(("User", "deploy")));
+        assert_eq!(
+            split_keyword("\tUser\tdeploy  # the deploy user"),
+            Some(("User", "deploy"))
+        );
+        assert_eq!(
+            split_keyword("HostName host#1"),
+            Some(("HostName", "host#1"))
+        );
+        assert_eq!(split_keyword("# Host commented"), None);
+        assert_eq!(split_keyword("   "), None);
+    }
+
+    #[test]
+    fn host_lines_declare_every_alias() {
+        let hosts = parse(
+            "Host a b c # three aliases\n  HostName shared.example.com\n  User=deploy\n  Port 2222\n",
+        );
+        let names: Vec<(&str, Option<String>)> = hosts
+            .iter()
+            .map(|host| (host.alias.as_str(), host.resolved_name()))
+            .collect();
+        let resolved = Some("deploy@shared.example.com:2222".to_owned());
+        assert_eq!(
+            names,
+            [
+                ("a", resolved.clone()),
+                ("b", resolved.clone()),
+                ("c", resolved)
+            ]
+        );
+    }
+
+    #[test]
+    fn patterns_are_not_hosts() {
+        let hosts = parse(
+            "Host *\n  User root\nHost web-? !bastion db\n  HostName db.example.com\nHost *.internal\n",
+        );
+        assert_eq!(hosts.len(), 1);
+        assert_eq!(hosts[0].alias, "db");
+        assert_eq!(hosts[0].user, None);
+        assert_eq!(hosts[0].host_name.as_deref(), Some("db.example.com"));
+    }
+
+    #[test]
+    fn includes_globs_relative_and_home_paths_in_place() {
+        let home = ssh_tree(&[
+            (
+                "config",
+                "Host first\nInclude conf.d/*.conf ~/extra/hosts\nHost last\n",
+            ),
+            ("conf.d/b.conf", "Host b\n"),
+            ("conf.d/a.conf", "Host a\n"),
+            ("conf.d/notes.txt", "Host skipped\n"),
+            ("../extra/hosts", "Host extra\n"),
+        ]);
+        assert_eq!(
+            aliases(&home).unwrap(),
+            ["first", "a", "b", "extra", "last"]
+        );
+    }
+
+    #[test]
+    fn missing_includes_are_skipped() {
+        let home = ssh_tree(&[("config", "Include missing/*\nHost alpha\n")]);
+        assert_eq!(aliases(&home).unwrap(), ["alpha"]);
+    }
+
+    #[test]
+    fn include_cycles_are_skipped() {
+        let home = ssh_tree(&[
+            ("config", "Host alpha\nInclude loop\n"),
+            ("loop", "Host beta\nInclude config loop\n"),
+        ]);
+        assert_eq!(aliases(&home).unwrap(), ["alpha", "beta"]);
+    }
+
+    #[test]
+    fn deeply_nested_includes_fail() {
+        let mut files: Vec<(String, String)> = (0..MAX_INCLUDE_DEPTH + 1)
+            .map(|i| (format!("nested/{i}"), format!("Include nested/{}\n", i + 1)))
+            .collect();
+        files.push(("config".to_owned(), "Include nested/0\n".to_owned()));
+        let files: Vec<(&str, &str)> = files
+            .iter()
+            .map(|(path, contents)| (path.as_str(), contents.as_str()))
+            .collect();
+        let error = aliases(&ssh_tree(&files)).unwrap_err();
+        assert!(error.to_string().contains("Too many nested Include"));
+    }
+}
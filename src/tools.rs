@@ -0,0 +1,53 @@
+use crate::shell::LoggedCommand;
+use std::io;
+use std::process::{Command, Stdio};
+
+/// Maps the error of spawning `program` to an actionable message when it is not installed
+pub fn tool_error(program: &'static str) -> impl Fn(io::Error) -> anyhow::Error {
+    move |error| {
+        if error.kind() != io::ErrorKind::NotFound {
+            return error.into();
+        }
+
+        anyhow::anyhow!(
+            "{program} is required but not found; {}",
+            install_hint(program)
+        )
+    }
+}
+
+pub fn install_hint(program: &str) -> String {
+    match install_url(program) {
+        Some(url) => format!("install it from {url}"),
+        None => "make sure it is on your PATH".to_owned(),
+    }
+}
+
+/// First line `program` prints when asked for its version, `ssh` prints it on stderr
+pub fn tool_version(program: &str) -> io::Result<String> {
+    let flag = if program == "ssh" { "-V" } else { "--version" };
+    let output = Command::new(program)
+        .arg(flag)
+        .stdin(Stdio::null())
+        .logged_output()?;
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(stdout
+        .lines()
+        .chain(stderr.lines())
+        .map(str::trim)
+        .find(|line| !line.is_empty())
+        .unwrap_or("unknown version")
+        .to_owned())
+}
+
+fn install_url(program: &str) -> Option<&'static str> {
+    match program {
+        "fzf" => Some("https://github.com/junegunn/fzf#installation"),
+        "gum" => Some("https://github.com/charmbracelet/gum#installation"),
+        "ssh" => Some("https://www.openssh.com/portable.html"),
+        "rsync" => Some("https://rsync.samba.org/download.html"),
+        _ => None,
+    }
+}
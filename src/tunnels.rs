@@ -0,0 +1,93 @@
+use crate::shell::LoggedCommand;
+use crate::{cache_dir, write_atomically};
+use anyhow::Result;
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// A tunnel started with `--background`, its ssh process outlives rpio
+#[derive(Clone, Serialize, Deserialize)]
+pub struct BackgroundTunnel {
+    pub pid: u32,
+    pub host: String,
+    pub app_name: String,
+    pub host_port: u16,
+    pub target: String,
+    pub started_at: i64, // unix timestamp
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct TunnelsState {
+    #[serde(default)]
+    tunnels: Vec<BackgroundTunnel>,
+}
+
+pub fn tunnels_state_path() -> PathBuf {
+    cache_dir().join("tunnels.toml")
+}
+
+/// Also checks that the process is still ssh, so a pid reused by another process is never signalled
+fn is_alive(pid: u32) -> bool {
+    Command::new("ps")
+        .args(["-o", "comm=", "-p", &pid.to_string()])
+        .stderr(Stdio::null())
+        .logged_output()
+        .is_ok_and(|output| {
+            output.status.success()
+                && String::from_utf8_lossy(&output.stdout)
+                    .trim()
+                    .rsplit('/')
+                    .next()
+                    .is_some_and(|command| command == "ssh")
+        })
+}
+
+/// Background tunnels that are still running, tunnels that exited in the meantime are forgotten
+pub fn load_tunnels() -> Result<Vec<BackgroundTunnel>> {
+    let state: TunnelsState = fs::read_to_string(tunnels_state_path())
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+        .unwrap_or_default();
+
+    let total = state.tunnels.len();
+    let tunnels: Vec<BackgroundTunnel> = state
+        .tunnels
+        .into_iter()
+        .filter(|tunnel| is_alive(tunnel.pid))
+        .collect();
+    if tunnels.len() != total {
+        save_tunnels(&tunnels)?;
+    }
+
+    Ok(tunnels)
+}
+
+fn save_tunnels(tunnels: &[BackgroundTunnel]) -> Result<()> {
+    let state = TunnelsState {
+        tunnels: tunnels.to_vec(),
+    };
+    write_atomically(&tunnels_state_path(), &toml::to_string_pretty(&state)?)
+}
+
+pub fn register_tunnel(tunnel: BackgroundTunnel) -> Result<()> {
+    let mut tunnels = load_tunnels()?;
+    tunnels.push(tunnel);
+    save_tunnels(&tunnels)
+}
+
+pub fn stop_tunnel(tunnel: &BackgroundTunnel) -> Result<()> {
+    let status = Command::new("kill")
+        .arg(tunnel.pid.to_string())
+        .logged_status()?;
+    if !status.success() {
+        bail!("Could not stop the tunnel with pid {}", tunnel.pid);
+    }
+
+    let tunnels: Vec<BackgroundTunnel> = load_tunnels()?
+        .into_iter()
+        .filter(|t| t.pid != tunnel.pid)
+        .collect();
+    save_tunnels(&tunnels)
+}